@@ -0,0 +1,630 @@
+// The compositor operations OmaVeil's commands are built from: listing
+// windows and monitors, finding what has focus, and hiding, moving and
+// focusing windows. Commands reach the compositor through `backend()`
+// rather than issuing dispatches themselves, so supporting another
+// compositor means adding a `Backend` implementation, and command logic can
+// run against a stand-in.
+//
+// `Hyprland` talks hyprctl through `crate::hypr`; `Sway` speaks the i3 IPC
+// protocol (see `crate::sway`), `Niri` niri's JSON one (`crate::niri`) and
+// `River` drives riverctl (`crate::river`). Any other wlroots compositor
+// gets `ForeignToplevel`, which only needs the foreign-toplevel protocols
+// (`crate::toplevel`). Extras a compositor may lack (batched moves,
+// swapping tiles) have default implementations that report "unsupported"
+// so callers fall back to the plain path.
+
+use crate::{
+    dispatch, hypr, hypr::hyprctl, niri::Niri, river::River, sway::Sway, toplevel::ForeignToplevel,
+    Client, Fullscreen, Monitor, Rect, WorkspaceTarget,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{env, io, sync::OnceLock};
+
+pub trait Backend: Send + Sync {
+    /// Compositor name for messages ("Hyprland").
+    fn name(&self) -> &'static str;
+
+    /// `Client::workspace_name` of windows that are hidden.
+    fn hidden_workspace(&self) -> String;
+
+    /// Where a window of `class` is hidden, when that depends on the class.
+    fn hidden_workspace_for(&self, _class: &str) -> String {
+        self.hidden_workspace()
+    }
+
+    /// Whether `workspace_name` is one windows are hidden on.
+    fn is_hidden(&self, workspace_name: &str) -> bool {
+        workspace_name == self.hidden_workspace()
+    }
+
+    /// Every window the compositor knows about, or `None` if it couldn't be
+    /// asked (callers must not treat that as "no windows are alive").
+    fn clients(&self) -> Option<Vec<Client>>;
+
+    /// Connected monitors; empty if they couldn't be listed.
+    fn monitors(&self) -> Vec<Monitor>;
+
+    /// The focused window, or `None` if nothing has focus or the query
+    /// failed (logged under `context`).
+    fn active_window(&self, context: &str) -> io::Result<Option<Client>>;
+
+    /// The focused workspace, or `None` if it couldn't be determined.
+    fn active_workspace(&self, context: &str) -> io::Result<Option<i64>>;
+
+    /// Moves a window onto hidden workspace `workspace` (as returned by
+    /// `hidden_workspace_for`), with `follow` switching the view along so
+    /// the move is animated. `Err` carries the compositor's reason for
+    /// refusing.
+    fn hide(&self, address: &str, workspace: &str, follow: bool) -> io::Result<Result<(), String>>;
+
+    /// Moves a window to `workspace`; `silent` leaves focus where it is.
+    /// Returns whether the compositor accepted.
+    fn move_to_workspace(
+        &self,
+        context: &str,
+        address: &str,
+        workspace: &WorkspaceTarget,
+        silent: bool,
+    ) -> io::Result<bool>;
+
+    fn focus(&self, context: &str, address: &str) -> io::Result<bool>;
+
+    fn close(&self, context: &str, address: &str) -> io::Result<bool>;
+
+    /// Floats a window (`floating` says whether it already is) and places
+    /// it at `rect`.
+    fn float_at(
+        &self,
+        context: &str,
+        address: &str,
+        floating: bool,
+        rect: Rect,
+    ) -> io::Result<bool>;
+
+    /// Called after a followed `hide` once the state is saved, to take the
+    /// view off hidden workspace `workspace` again.
+    fn leave_hidden(&self, _context: &str, _workspace: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// The workspace called `name` as a move target, or `None` if there is
+    /// no such workspace (or workspaces have no names).
+    fn workspace_named(&self, _context: &str, _name: &str) -> io::Result<Option<WorkspaceTarget>> {
+        Ok(None)
+    }
+
+    /// How to address the workspace numbered `id` (as reported for windows,
+    /// monitors and `active_workspace`) in a move.
+    fn workspace_target(&self, _context: &str, id: i64) -> io::Result<WorkspaceTarget> {
+        Ok(WorkspaceTarget::Number(id))
+    }
+
+    /// Moves and focuses all `addresses` in one request. `Ok(false)` means
+    /// nothing was (knowingly) done, and the caller should go one by one.
+    fn move_all(
+        &self,
+        _context: &str,
+        _addresses: &[&str],
+        _workspace: &WorkspaceTarget,
+    ) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    /// Hides every `(address, hidden workspace)` pair in one request,
+    /// without following. `Ok(false)` means nothing was (knowingly) done,
+    /// and the caller should go one by one.
+    fn hide_all(&self, _context: &str, _windows: &[(&str, &str)]) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    /// Makes the focused window `address` fullscreen (or maximized) again.
+    /// `Ok(false)` if unsupported.
+    fn set_fullscreen(
+        &self,
+        _context: &str,
+        _address: &str,
+        _mode: Fullscreen,
+    ) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    /// Pins a floating window to every workspace. `Ok(false)` if
+    /// unsupported.
+    fn pin(&self, _context: &str, _address: &str) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    /// Unpins a window before it's hidden, so it doesn't stay on screen.
+    /// `Ok(false)` if unsupported or it wasn't pinned.
+    fn unpin(&self, _context: &str, _address: &str) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    /// Adds the focused window `address` to `target`'s tab group, making
+    /// `target` a group first if it isn't one. `Ok(false)` if unsupported
+    /// or the two couldn't be grouped.
+    fn join_group(&self, _context: &str, _address: &str, _target: &Client) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    /// Swaps the focused tile with its neighbor in `direction` ("l", "r",
+    /// "u" or "d"). `Ok(false)` if unsupported.
+    fn swap(&self, _context: &str, _direction: &str) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+pub struct Hyprland;
+
+impl Backend for Hyprland {
+    fn name(&self) -> &'static str {
+        "Hyprland"
+    }
+
+    fn hidden_workspace(&self) -> String {
+        format!("special:{}", crate::config().special_workspace)
+    }
+
+    // `special:minimum-firefox` with `special_per_class`
+    fn hidden_workspace_for(&self, class: &str) -> String {
+        let suffix: String = class
+            .to_lowercase()
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '-' })
+            .collect();
+        let suffix = suffix.trim_matches('-');
+        if !crate::config().special_per_class || suffix.is_empty() {
+            return self.hidden_workspace();
+        }
+        format!("{}-{}", self.hidden_workspace(), suffix)
+    }
+
+    // Per-class workspaces count whether or not `special_per_class` is on
+    // right now, so windows hidden before it was switched aren't lost
+    fn is_hidden(&self, workspace_name: &str) -> bool {
+        let base = self.hidden_workspace();
+        workspace_name
+            .strip_prefix(&base)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('-'))
+    }
+
+    fn clients(&self) -> Option<Vec<Client>> {
+        match query::<Vec<HyprClient>>("clients", "clients") {
+            Ok(clients) => Some(clients?.into_iter().map(HyprClient::into_client).collect()),
+            Err(e) => {
+                crate::log_error(&format!("clients: failed to run hyprctl clients — {}", e));
+                None
+            }
+        }
+    }
+
+    fn monitors(&self) -> Vec<Monitor> {
+        query::<Vec<HyprMonitor>>("monitors", "monitors")
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+            .into_iter()
+            .map(HyprMonitor::into_monitor)
+            .collect()
+    }
+
+    fn active_window(&self, context: &str) -> io::Result<Option<Client>> {
+        // `activewindow -j` prints `{}` when nothing is focused
+        Ok(query::<HyprClient>(context, "activewindow")?
+            .map(HyprClient::into_client)
+            .filter(|c| !c.address.is_empty()))
+    }
+
+    fn active_workspace(&self, context: &str) -> io::Result<Option<i64>> {
+        Ok(query::<HyprWorkspace>(context, "activeworkspace")?.map(|ws| ws.id))
+    }
+
+    fn hide(&self, address: &str, workspace: &str, follow: bool) -> io::Result<Result<(), String>> {
+        let dispatcher = if follow {
+            "movetoworkspace"
+        } else {
+            "movetoworkspacesilent"
+        };
+        let arg = format!("{},address:{}", workspace, address);
+        let special = workspace.trim_start_matches("special:");
+        let attempt = || -> io::Result<Option<String>> {
+            let output = hyprctl(&["dispatch", dispatcher, &arg])?;
+            let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            // hyprctl answers "ok" on success and the reason otherwise
+            if output.success && stdout == "ok" {
+                return Ok(None);
+            }
+            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            Ok(Some(format!("stdout={} stderr={}", stdout, stderr)))
+        };
+
+        let Some(reason) = attempt()? else {
+            return Ok(Ok(()));
+        };
+        crate::log_error(&format!(
+            "minimize: {} failed for address={}, retrying — {}",
+            dispatcher, address, reason
+        ));
+
+        // Hyprland occasionally rejects the move while the special
+        // workspace is in an odd state; toggling it open clears that
+        dispatch("minimize", "togglespecialworkspace", special)?;
+        let retry = attempt()?;
+        dispatch("minimize", "togglespecialworkspace", special)?;
+
+        Ok(match retry {
+            None => Ok(()),
+            Some(reason) => Err(reason),
+        })
+    }
+
+    fn move_to_workspace(
+        &self,
+        context: &str,
+        address: &str,
+        workspace: &WorkspaceTarget,
+        silent: bool,
+    ) -> io::Result<bool> {
+        let dispatcher = if silent {
+            "movetoworkspacesilent"
+        } else {
+            "movetoworkspace"
+        };
+        dispatch(
+            context,
+            dispatcher,
+            &format!("{},address:{}", dispatch_target(workspace), address),
+        )
+    }
+
+    fn focus(&self, context: &str, address: &str) -> io::Result<bool> {
+        dispatch(context, "focuswindow", &format!("address:{}", address))
+    }
+
+    fn close(&self, context: &str, address: &str) -> io::Result<bool> {
+        dispatch(context, "closewindow", &format!("address:{}", address))
+    }
+
+    fn float_at(
+        &self,
+        context: &str,
+        address: &str,
+        floating: bool,
+        rect: Rect,
+    ) -> io::Result<bool> {
+        let address = format!("address:{}", address);
+        if !floating {
+            dispatch(context, "setfloating", &address)?;
+        }
+        dispatch(
+            context,
+            "resizewindowpixel",
+            &format!("exact {} {},{}", rect.width, rect.height, address),
+        )?;
+        dispatch(
+            context,
+            "movewindowpixel",
+            &format!("exact {} {},{}", rect.x, rect.y, address),
+        )
+    }
+
+    fn leave_hidden(&self, context: &str, workspace: &str) -> io::Result<()> {
+        let special = workspace.trim_start_matches("special:");
+        dispatch(context, "togglespecialworkspace", special).map(|_| ())
+    }
+
+    fn workspace_named(&self, context: &str, name: &str) -> io::Result<Option<WorkspaceTarget>> {
+        Ok(workspaces(context)?
+            .into_iter()
+            .find(|ws| ws.name == name)
+            .map(|ws| match ws.id {
+                id if id > 0 => WorkspaceTarget::Number(id),
+                _ => WorkspaceTarget::Named(ws.name),
+            }))
+    }
+
+    // Named workspaces have negative ids, which only work as a target by name
+    fn workspace_target(&self, context: &str, id: i64) -> io::Result<WorkspaceTarget> {
+        if id > 0 {
+            return Ok(WorkspaceTarget::Number(id));
+        }
+        Ok(workspaces(context)?
+            .into_iter()
+            .find(|ws| ws.id == id && !ws.name.is_empty())
+            .map_or(WorkspaceTarget::Number(id), |ws| {
+                WorkspaceTarget::Named(ws.name)
+            }))
+    }
+
+    fn move_all(
+        &self,
+        context: &str,
+        addresses: &[&str],
+        workspace: &WorkspaceTarget,
+    ) -> io::Result<bool> {
+        let workspace = dispatch_target(workspace);
+        let commands: Vec<String> = addresses
+            .iter()
+            .flat_map(|address| {
+                [
+                    format!("dispatch movetoworkspace {},address:{}", workspace, address),
+                    format!("dispatch focuswindow address:{}", address),
+                ]
+            })
+            .collect();
+        batch_accepted(context, &commands)
+    }
+
+    fn hide_all(&self, context: &str, windows: &[(&str, &str)]) -> io::Result<bool> {
+        let commands: Vec<String> = windows
+            .iter()
+            .map(|(address, workspace)| {
+                format!(
+                    "dispatch movetoworkspacesilent {},address:{}",
+                    workspace, address
+                )
+            })
+            .collect();
+        batch_accepted(context, &commands)
+    }
+
+    // `moveintogroup` takes a direction, so this goes by where the window
+    // landed next to the group
+    fn join_group(&self, context: &str, address: &str, target: &Client) -> io::Result<bool> {
+        if !target.grouped {
+            // `togglegroup` acts on the focused window
+            if !self.focus(context, &target.address)? || !dispatch(context, "togglegroup", "")? {
+                return Ok(false);
+            }
+            self.focus(context, address)?;
+        }
+        let live = self.clients().unwrap_or_default();
+        let rect_of = |addr: &str| live.iter().find(|c| c.address == addr)?.rect;
+        let side = rect_of(address)
+            .zip(rect_of(&target.address))
+            .and_then(|(window, group)| crate::side_of(&window, &group));
+        let Some((side, _)) = side else {
+            crate::log_error(&format!(
+                "{}: address={} isn't next to address={}, can't group them",
+                context, address, target.address
+            ));
+            return Ok(false);
+        };
+        dispatch(context, "moveintogroup", crate::opposite(side))
+    }
+
+    fn swap(&self, context: &str, direction: &str) -> io::Result<bool> {
+        dispatch(context, "swapwindow", direction)
+    }
+
+    // `fullscreen` acts on the focused window: 0 is real fullscreen, 1
+    // maximize
+    fn set_fullscreen(&self, context: &str, _address: &str, mode: Fullscreen) -> io::Result<bool> {
+        let arg = match mode {
+            Fullscreen::Full => "0",
+            Fullscreen::Maximized => "1",
+        };
+        dispatch(context, "fullscreen", arg)
+    }
+
+    fn pin(&self, context: &str, address: &str) -> io::Result<bool> {
+        dispatch(context, "pin", &format!("address:{}", address))
+    }
+
+    // `pin` toggles, so only dispatch it for a window that's pinned right now
+    fn unpin(&self, context: &str, address: &str) -> io::Result<bool> {
+        let pinned = self
+            .clients()
+            .unwrap_or_default()
+            .iter()
+            .any(|c| c.address == address && c.pinned);
+        if !pinned {
+            return Ok(false);
+        }
+        dispatch(context, "pin", &format!("address:{}", address))
+    }
+}
+
+static BACKEND: OnceLock<Box<dyn Backend>> = OnceLock::new();
+
+/// The compositor OmaVeil is running under: Hyprland whenever its
+/// instance signature is set (and always under test), otherwise
+/// Sway or niri if `$SWAYSOCK` / `$NIRI_SOCKET` points at a live session,
+/// river if `$XDG_CURRENT_DESKTOP` says so, then the generic
+/// foreign-toplevel backend if its tools are installed, and Hyprland again
+/// as the last resort.
+pub fn backend() -> &'static dyn Backend {
+    BACKEND
+        .get_or_init(|| {
+            let hyprland = cfg!(test) || env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some();
+            if hyprland {
+                return Box::new(Hyprland);
+            }
+            if let Some(sway) = Sway::locate() {
+                return Box::new(sway);
+            }
+            if let Some(niri) = Niri::locate() {
+                return Box::new(niri);
+            }
+            if River::detect() {
+                return Box::new(River);
+            }
+            match ForeignToplevel::available() {
+                true => Box::new(ForeignToplevel),
+                false => Box::new(Hyprland),
+            }
+        })
+        .as_ref()
+}
+
+/// Sends `commands` as one hyprctl batch; true if Hyprland accepted every
+/// one of them. Anything less is logged under `context`.
+fn batch_accepted(context: &str, commands: &[String]) -> io::Result<bool> {
+    let reply = hypr::batch(commands)?;
+    let answers = String::from_utf8_lossy(&reply.stdout);
+    // One "ok" per command, separated by blank lines on newer releases
+    if !reply.success
+        || answers.matches("ok").count() != commands.len()
+        || !answers.replace("ok", "").trim().is_empty()
+    {
+        crate::log_error(&format!(
+            "{}: batch not fully accepted, retrying one by one — stdout={} stderr={}",
+            context,
+            answers.trim().replace('\n', " | "),
+            String::from_utf8_lossy(&reply.stderr).trim()
+        ));
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// A workspace as Hyprland's dispatchers take it: `3` or `name:notes`.
+fn dispatch_target(workspace: &WorkspaceTarget) -> String {
+    match workspace {
+        WorkspaceTarget::Number(number) => number.to_string(),
+        WorkspaceTarget::Named(name) => format!("name:{}", name),
+    }
+}
+
+/// `hyprctl -j <what>` read into a `T`. `None` if hyprctl reported a
+/// failure or the reply didn't have the expected shape, both logged under
+/// `context`.
+fn query<T: DeserializeOwned>(context: &str, what: &str) -> io::Result<Option<T>> {
+    let output = hyprctl(&["-j", what])?;
+    if !output.success {
+        crate::log_error(&format!(
+            "{}: hyprctl {} failed — {}",
+            context,
+            what,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+        return Ok(None);
+    }
+    // Titles can carry bytes that aren't valid UTF-8; replace rather than fail
+    match serde_json::from_str(&String::from_utf8_lossy(&output.stdout)) {
+        Ok(reply) => Ok(Some(reply)),
+        Err(e) => {
+            crate::log_error(&format!(
+                "{}: could not parse hyprctl {} output — {}",
+                context, what, e
+            ));
+            Ok(None)
+        }
+    }
+}
+
+/// `hyprctl workspaces -j`; empty if Hyprland couldn't answer.
+fn workspaces(context: &str) -> io::Result<Vec<HyprWorkspace>> {
+    Ok(query(context, "workspaces")?.unwrap_or_default())
+}
+
+/// A window in `hyprctl clients -j` or `activewindow -j` (which prints `{}`
+/// when nothing has focus, hence the defaults).
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct HyprClient {
+    address: String,
+    class: String,
+    title: String,
+    workspace: HyprWorkspace,
+    floating: bool,
+    at: Option<(i64, i64)>,
+    size: Option<(i64, i64)>,
+    fullscreen: Option<HyprFullscreen>,
+    fullscreen_mode: Option<i64>,
+    pinned: bool,
+    /// Addresses of the window's tab group, itself included.
+    grouped: Vec<String>,
+}
+
+/// 0.42 made `fullscreen` a state number (1 maximized, 2 fullscreen, 3
+/// both); before that it was a bool, with the kind in `fullscreenMode`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HyprFullscreen {
+    State(i64),
+    Flag(bool),
+}
+
+impl HyprClient {
+    fn into_client(self) -> Client {
+        let rect = self
+            .at
+            .zip(self.size)
+            .filter(|(_, (width, height))| *width > 0 && *height > 0)
+            .map(|((x, y), (width, height))| Rect {
+                x,
+                y,
+                width,
+                height,
+            });
+        let fullscreen = match self.fullscreen {
+            Some(HyprFullscreen::State(1)) => Some(Fullscreen::Maximized),
+            Some(HyprFullscreen::State(state)) if state >= 2 => Some(Fullscreen::Full),
+            Some(HyprFullscreen::Flag(true)) => match self.fullscreen_mode {
+                Some(1) => Some(Fullscreen::Maximized),
+                _ => Some(Fullscreen::Full),
+            },
+            _ => None,
+        };
+        Client {
+            address: self.address,
+            class: self.class,
+            title: self.title,
+            workspace_id: self.workspace.id,
+            workspace_name: self.workspace.name,
+            floating: self.floating,
+            rect,
+            fullscreen,
+            pinned: self.pinned,
+            grouped: !self.grouped.is_empty(),
+        }
+    }
+}
+
+/// A workspace in `hyprctl workspaces -j` and `activeworkspace -j`, and a
+/// monitor's `activeWorkspace`.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct HyprWorkspace {
+    id: i64,
+    name: String,
+}
+
+/// An output in `hyprctl monitors -j`, its size in pixels.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HyprMonitor {
+    name: String,
+    x: i64,
+    y: i64,
+    width: i64,
+    height: i64,
+    scale: Option<f64>,
+    #[serde(default)]
+    transform: i64,
+    #[serde(default)]
+    active_workspace: HyprWorkspace,
+}
+
+impl HyprMonitor {
+    fn into_monitor(self) -> Monitor {
+        let scale = self.scale.filter(|s| *s > 0.0).unwrap_or(1.0);
+        let mut width = (self.width as f64 / scale).round() as i64;
+        let mut height = (self.height as f64 / scale).round() as i64;
+        if self.transform % 2 == 1 {
+            std::mem::swap(&mut width, &mut height);
+        }
+        Monitor {
+            name: self.name,
+            active_workspace: self.active_workspace.id,
+            transform: self.transform,
+            rect: Rect {
+                x: self.x,
+                y: self.y,
+                width,
+                height,
+            },
+        }
+    }
+}
@@ -0,0 +1,540 @@
+// User configuration, read from `$XDG_CONFIG_HOME/omaveil/config.toml`
+// (falling back to `~/.config/omaveil/config.toml`).
+//
+// Only the flat `key = value` subset of TOML is understood, which is all
+// OmaVeil needs. A missing file means defaults; a bad line is logged and
+// skipped so a typo never stops minimize/restore from working.
+//
+// Layers apply in order, later ones winning: defaults, the file,
+// `OMAVEIL_<KEY>` environment variables, then `--set key=value` flags. Each
+// key remembers which layer it came from for `config print --effective`.
+
+use crate::pattern::Pattern;
+use std::{collections::HashMap, env, fs, path::PathBuf};
+
+/// Every key `set` understands, in the order `config print` lists them.
+pub const KEYS: [&str; 29] = [
+    "prune_on_restore",
+    "restore_to_origin_monitor",
+    "cycle_order",
+    "capture_delay_ms",
+    "picker",
+    "picker_class",
+    "picker_group_stashes",
+    "minimize_visible",
+    "special_workspace",
+    "special_per_class",
+    "exclude",
+    "never_minimize",
+    "never_minimize_titles",
+    "notify_never_minimize",
+    "idle_minimize_minutes",
+    "idle_minimize_classes",
+    "idle_minimize_exclude",
+    "sweep_on_leave",
+    "max_windows",
+    "previews",
+    "preview_background",
+    "preview_embed",
+    "grid_columns",
+    "title_max_len",
+    "sound_minimize",
+    "sound_restore",
+    "sound_player",
+    "release_url",
+    "log_level",
+];
+
+/// How much goes into the log file. Each level includes the ones before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Info,
+    /// Adds how long each external command took.
+    Debug,
+}
+
+/// Which end of the stack `cycle` starts from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CycleOrder {
+    Oldest,
+    Newest,
+}
+
+/// The layer a config value was taken from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File,
+    Env,
+    Flag,
+}
+
+impl Source {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Source::Default => "default",
+            Source::File => "file",
+            Source::Env => "env",
+            Source::Flag => "flag",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Drop state entries whose window no longer exists before restoring.
+    pub prune_on_restore: bool,
+    /// Restore windows to the monitor they were minimized from, as if
+    /// `--origin-monitor` were always passed.
+    pub restore_to_origin_monitor: bool,
+    /// Whether `cycle` goes from the oldest minimized window to the newest,
+    /// or the other way round.
+    pub cycle_order: CycleOrder,
+    /// Milliseconds to wait before grabbing a preview, letting open/close
+    /// animations settle.
+    pub capture_delay_ms: u64,
+    /// dmenu-style picker used by `restore`/`pick`: walker, fuzzel, rofi,
+    /// wofi, or any other dmenu-compatible command.
+    pub picker: String,
+    /// Window class of the picker, skipped by `minimize`. Derived from
+    /// `picker` when unset.
+    pub picker_class: Option<String>,
+    /// List the windows of each `--group` together in the restore picker,
+    /// with a line that restores the whole group.
+    pub picker_group_stashes: bool,
+    /// Animate the window onto the special workspace before hiding it,
+    /// instead of moving it silently.
+    pub minimize_visible: bool,
+    /// Special workspace windows are hidden on, without the `special:`
+    /// prefix (also the named workspace used on niri).
+    pub special_workspace: String,
+    /// Hide each class on its own special workspace,
+    /// `special:<special_workspace>-<class>`.
+    pub special_per_class: bool,
+    /// Classes `minimize-all`/`minimize-others` leave alone (case-insensitive
+    /// substring match).
+    pub exclude: Vec<String>,
+    /// Classes no command ever minimizes (case-insensitive substring match).
+    pub never_minimize: Vec<String>,
+    /// Title patterns (see `crate::pattern`) no command ever minimizes.
+    pub never_minimize_titles: Vec<String>,
+    /// Send a desktop notification when a minimize is refused because of
+    /// the two lists above.
+    pub notify_never_minimize: bool,
+    /// `watch` minimizes windows that haven't had focus for this many
+    /// minutes (0 means never).
+    pub idle_minimize_minutes: u64,
+    /// Classes idle minimizing applies to (case-insensitive substring
+    /// match); empty means every class.
+    pub idle_minimize_classes: Vec<String>,
+    /// Classes idle minimizing never applies to.
+    pub idle_minimize_exclude: Vec<String>,
+    /// Classes `watch` minimizes off a workspace you switch away from and
+    /// restores when you come back (case-insensitive substring match).
+    pub sweep_on_leave: Vec<String>,
+    /// Refuse to minimize past this many windows (0 means no limit).
+    pub max_windows: usize,
+    /// Capture a thumbnail of each window as it's minimized. Off skips
+    /// grim and convert entirely, as `minimize --no-preview` does.
+    pub previews: bool,
+    /// Fill behind thumbnails whose aspect ratio doesn't match the preview:
+    /// a `#rrggbb` hex color or `transparent`.
+    pub preview_background: String,
+    /// Also store each thumbnail base64-encoded in its state entry, for
+    /// widgets that can't read files.
+    pub preview_embed: bool,
+    /// Thumbnails per row in `export-grid`.
+    pub grid_columns: u64,
+    /// Titles longer than this many characters are cut short with `…` in
+    /// the picker (0 means never). The full title is still stored.
+    pub title_max_len: usize,
+    /// Audio file played after a successful minimize / restore.
+    pub sound_minimize: Option<String>,
+    pub sound_restore: Option<String>,
+    /// Command used to play those files (`paplay`, `pw-play`, ...).
+    pub sound_player: String,
+    /// Release API endpoint queried by `version-check`.
+    pub release_url: String,
+    pub log_level: LogLevel,
+    /// Keys set by anything other than the defaults.
+    sources: HashMap<String, Source>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            prune_on_restore: true,
+            restore_to_origin_monitor: false,
+            cycle_order: CycleOrder::Oldest,
+            capture_delay_ms: 0,
+            max_windows: 0,
+            exclude: Vec::new(),
+            never_minimize: Vec::new(),
+            never_minimize_titles: Vec::new(),
+            notify_never_minimize: false,
+            idle_minimize_minutes: 0,
+            idle_minimize_classes: Vec::new(),
+            idle_minimize_exclude: Vec::new(),
+            sweep_on_leave: Vec::new(),
+            minimize_visible: false,
+            special_workspace: "minimum".to_string(),
+            special_per_class: false,
+            picker: "walker".to_string(),
+            picker_class: None,
+            picker_group_stashes: false,
+            previews: true,
+            preview_background: "#1a1b26".to_string(),
+            preview_embed: false,
+            grid_columns: 4,
+            title_max_len: 80,
+            sound_minimize: None,
+            sound_restore: None,
+            sound_player: "paplay".to_string(),
+            release_url: "https://api.github.com/repos/somtooo/OmaVeil/releases/latest".to_string(),
+            log_level: LogLevel::Info,
+            sources: HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn path() -> PathBuf {
+        let base = env::var_os("XDG_CONFIG_HOME")
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .unwrap_or_else(|| PathBuf::from("/etc"));
+        base.join("omaveil").join("config.toml")
+    }
+
+    /// Resolves every layer. `overrides` are `key=value` pairs from
+    /// `--set`; unlike a bad file or environment value, which is logged and
+    /// skipped, a bad override is returned as an error since it was typed
+    /// just now.
+    pub fn load(overrides: &[String]) -> Result<Config, String> {
+        let path = Config::path();
+        let mut config = match fs::read_to_string(&path) {
+            Ok(content) => {
+                let (config, errors) = Config::parse(&content);
+                for error in errors {
+                    crate::log_error(&format!("config: {} — {}", path.display(), error));
+                }
+                config
+            }
+            Err(_) => Config::default(),
+        };
+
+        for key in KEYS {
+            let var = format!("OMAVEIL_{}", key.to_ascii_uppercase());
+            let Ok(value) = env::var(&var) else {
+                continue;
+            };
+            if let Err(e) = config.set_loose(key, value.trim(), Source::Env) {
+                crate::log_error(&format!("config: ${} — {}", var, e));
+            }
+        }
+
+        for pair in overrides {
+            let Some((key, value)) = pair.split_once('=') else {
+                return Err(format!("--set expects key=value, got `{}`", pair));
+            };
+            config
+                .set_loose(key.trim(), value.trim(), Source::Flag)
+                .map_err(|e| format!("--set {}", e))?;
+        }
+        Ok(config)
+    }
+
+    /// Where `key`'s current value came from.
+    pub fn source(&self, key: &str) -> Source {
+        self.sources.get(key).copied().unwrap_or(Source::Default)
+    }
+
+    /// The current value of `key` written as TOML, or `None` for an
+    /// optional key that isn't set.
+    pub fn value(&self, key: &str) -> Option<String> {
+        let text = match key {
+            "prune_on_restore" => self.prune_on_restore.to_string(),
+            "restore_to_origin_monitor" => self.restore_to_origin_monitor.to_string(),
+            "cycle_order" => quote(match self.cycle_order {
+                CycleOrder::Oldest => "oldest",
+                CycleOrder::Newest => "newest",
+            }),
+            "capture_delay_ms" => self.capture_delay_ms.to_string(),
+            "picker" => quote(&self.picker),
+            "picker_class" => quote(self.picker_class.as_deref()?),
+            "picker_group_stashes" => self.picker_group_stashes.to_string(),
+            "minimize_visible" => self.minimize_visible.to_string(),
+            "special_workspace" => quote(&self.special_workspace),
+            "special_per_class" => self.special_per_class.to_string(),
+            "exclude" => quote_list(&self.exclude),
+            "never_minimize" => quote_list(&self.never_minimize),
+            "never_minimize_titles" => quote_list(&self.never_minimize_titles),
+            "notify_never_minimize" => self.notify_never_minimize.to_string(),
+            "idle_minimize_minutes" => self.idle_minimize_minutes.to_string(),
+            "idle_minimize_classes" => quote_list(&self.idle_minimize_classes),
+            "idle_minimize_exclude" => quote_list(&self.idle_minimize_exclude),
+            "sweep_on_leave" => quote_list(&self.sweep_on_leave),
+            "max_windows" => self.max_windows.to_string(),
+            "preview_background" => quote(&self.preview_background),
+            "previews" => self.previews.to_string(),
+            "preview_embed" => self.preview_embed.to_string(),
+            "grid_columns" => self.grid_columns.to_string(),
+            "title_max_len" => self.title_max_len.to_string(),
+            "sound_minimize" => quote(self.sound_minimize.as_deref()?),
+            "sound_restore" => quote(self.sound_restore.as_deref()?),
+            "sound_player" => quote(&self.sound_player),
+            "release_url" => quote(&self.release_url),
+            "log_level" => quote(match self.log_level {
+                LogLevel::Error => "error",
+                LogLevel::Info => "info",
+                LogLevel::Debug => "debug",
+            }),
+            _ => return None,
+        };
+        Some(text)
+    }
+
+    /// Parses `content`, returning the resulting config along with one
+    /// message per line that couldn't be applied.
+    pub fn parse(content: &str) -> (Config, Vec<String>) {
+        let mut config = Config::default();
+        let mut errors = Vec::new();
+
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                errors.push(format!("line {}: expected `key = value`", i + 1));
+                continue;
+            };
+
+            if let Err(e) = config.apply(key.trim(), strip_comment(value.trim()), Source::File) {
+                errors.push(format!("line {}: {}", i + 1, e));
+            }
+        }
+
+        (config, errors)
+    }
+
+    fn apply(&mut self, key: &str, value: &str, source: Source) -> Result<(), String> {
+        self.set(key, value)?;
+        self.sources.insert(key.to_string(), source);
+        Ok(())
+    }
+
+    /// Like `apply`, but a bare word is taken as a string, so
+    /// `OMAVEIL_PICKER=fuzzel` works without shell-quoting the quotes.
+    fn set_loose(&mut self, key: &str, value: &str, source: Source) -> Result<(), String> {
+        match self.apply(key, value, source) {
+            Err(_) if !value.starts_with(['"', '[']) && KEYS.contains(&key) => {
+                self.apply(key, &quote(value), source)
+            }
+            result => result,
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "prune_on_restore" => self.prune_on_restore = parse_bool(key, value)?,
+            "restore_to_origin_monitor" => self.restore_to_origin_monitor = parse_bool(key, value)?,
+            "cycle_order" => {
+                self.cycle_order = match parse_string(key, value)?.as_str() {
+                    "oldest" => CycleOrder::Oldest,
+                    "newest" => CycleOrder::Newest,
+                    other => {
+                        return Err(format!(
+                            "`{}` expects \"oldest\" or \"newest\", got `{}`",
+                            key, other
+                        ))
+                    }
+                }
+            }
+            "capture_delay_ms" => self.capture_delay_ms = parse_u64(key, value)?,
+            "minimize_visible" => self.minimize_visible = parse_bool(key, value)?,
+            "special_workspace" => {
+                let name = parse_string(key, value)?;
+                let name = name.strip_prefix("special:").unwrap_or(&name);
+                // Hyprland splits dispatcher arguments on commas
+                if name.is_empty() || name.contains([',', ' ', '\t']) {
+                    return Err(format!(
+                        "`{}` expects a name without spaces or commas, like \"minimum\", got `{}`",
+                        key, name
+                    ));
+                }
+                self.special_workspace = name.to_string();
+            }
+            "special_per_class" => self.special_per_class = parse_bool(key, value)?,
+            "exclude" => self.exclude = parse_string_list(key, value)?,
+            "never_minimize" => self.never_minimize = parse_string_list(key, value)?,
+            "never_minimize_titles" => {
+                let patterns = parse_string_list(key, value)?;
+                for pattern in &patterns {
+                    Pattern::new(pattern)
+                        .map_err(|e| format!("`{}`: bad pattern {:?} — {}", key, pattern, e))?;
+                }
+                self.never_minimize_titles = patterns;
+            }
+            "notify_never_minimize" => self.notify_never_minimize = parse_bool(key, value)?,
+            "idle_minimize_minutes" => self.idle_minimize_minutes = parse_u64(key, value)?,
+            "idle_minimize_classes" => self.idle_minimize_classes = parse_string_list(key, value)?,
+            "idle_minimize_exclude" => self.idle_minimize_exclude = parse_string_list(key, value)?,
+            "sweep_on_leave" => self.sweep_on_leave = parse_string_list(key, value)?,
+            "max_windows" => self.max_windows = parse_u64(key, value)? as usize,
+            "picker" => self.picker = parse_string(key, value)?,
+            "picker_class" => self.picker_class = Some(parse_string(key, value)?),
+            "picker_group_stashes" => self.picker_group_stashes = parse_bool(key, value)?,
+            "preview_background" => {
+                let color = parse_string(key, value)?;
+                let hex = color.strip_prefix('#').is_some_and(|digits| {
+                    matches!(digits.len(), 3 | 4 | 6 | 8)
+                        && digits.chars().all(|c| c.is_ascii_hexdigit())
+                });
+                if !hex && color != "transparent" {
+                    return Err(format!(
+                        "`{}` expects a hex color like \"#1a1b26\" or \"transparent\", got `{}`",
+                        key, color
+                    ));
+                }
+                self.preview_background = color;
+            }
+            "previews" => self.previews = parse_bool(key, value)?,
+            "preview_embed" => self.preview_embed = parse_bool(key, value)?,
+            "grid_columns" => self.grid_columns = parse_u64(key, value)?.max(1),
+            "title_max_len" => self.title_max_len = parse_u64(key, value)? as usize,
+            "sound_minimize" => self.sound_minimize = Some(parse_string(key, value)?),
+            "sound_restore" => self.sound_restore = Some(parse_string(key, value)?),
+            "sound_player" => self.sound_player = parse_string(key, value)?,
+            "release_url" => self.release_url = parse_string(key, value)?,
+            "log_level" => {
+                self.log_level = match parse_string(key, value)?.as_str() {
+                    "error" => LogLevel::Error,
+                    "info" => LogLevel::Info,
+                    "debug" => LogLevel::Debug,
+                    other => {
+                        return Err(format!(
+                            "`{}` expects \"error\", \"info\" or \"debug\", got `{}`",
+                            key, other
+                        ))
+                    }
+                }
+            }
+            _ => return Err(format!("unknown key `{}`", key)),
+        }
+        Ok(())
+    }
+}
+
+fn quote(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn quote_list(values: &[String]) -> String {
+    format!(
+        "[{}]",
+        values
+            .iter()
+            .map(|value| quote(value))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+// Drops a trailing `# comment`, ignoring any `#` inside a quoted string.
+fn strip_comment(value: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return value[..i].trim_end(),
+            _ => {}
+        }
+    }
+    value
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("`{}` expects true or false, got `{}`", key, value)),
+    }
+}
+
+fn parse_u64(key: &str, value: &str) -> Result<u64, String> {
+    value
+        .parse()
+        .map_err(|_| format!("`{}` expects a non-negative integer, got `{}`", key, value))
+}
+
+/// A `["a", "b"]` array of strings; a single quoted string is accepted too.
+fn parse_string_list(key: &str, value: &str) -> Result<Vec<String>, String> {
+    let Some(inner) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) else {
+        return parse_string(key, value).map(|s| vec![s]);
+    };
+
+    let mut items = Vec::new();
+    let mut start = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in inner.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                items.push(&inner[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&inner[start..]);
+
+    items
+        .into_iter()
+        .map(str::trim)
+        // Allow a trailing comma
+        .filter(|item| !item.is_empty())
+        .map(|item| parse_string(key, item))
+        .collect()
+}
+
+fn parse_string(key: &str, value: &str) -> Result<String, String> {
+    let inner = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .ok_or_else(|| format!("`{}` expects a quoted string, got `{}`", key, value))?;
+
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some(other) => out.push(other),
+                None => return Err(format!("`{}` ends with a dangling backslash", key)),
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,345 @@
+// The one place OmaVeil talks to Hyprland.
+//
+// Every query and dispatch goes through the `Hyprctl` trait so the transport
+// can be swapped out. Normally that's Hyprland's request socket, with the
+// real `hyprctl` binary as a fallback when the socket can't be found or
+// reached. Tests get a fake instead (`fake_dir`), which answers queries from
+// canned `<dir>/<query>.json` files and appends every dispatch to
+// `<dir>/dispatch.log`, so behaviour can be exercised without a compositor.
+//
+// Hyprland's event stream (`.socket2.sock`) is read through `events()`; under
+// test, the lines of `<dir>/events` are read instead.
+
+use serde::{de::IgnoredAny, Deserialize};
+#[cfg(test)]
+use std::fs::{self, OpenOptions};
+use std::{
+    env,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    process::Command,
+    sync::OnceLock,
+    time::Duration,
+};
+
+/// What a hyprctl invocation produced.
+pub struct Reply {
+    pub success: bool,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+pub trait Hyprctl: Send + Sync {
+    /// Runs the equivalent of `hyprctl <args>`.
+    fn run(&self, args: &[&str]) -> io::Result<Reply>;
+}
+
+/// Shells out to the `hyprctl` binary on `$PATH`.
+struct Cli;
+
+impl Hyprctl for Cli {
+    fn run(&self, args: &[&str]) -> io::Result<Reply> {
+        let output = Command::new("hyprctl").args(args).output()?;
+        Ok(Reply {
+            success: output.status.success(),
+            stdout: output.stdout,
+            stderr: output.stderr,
+        })
+    }
+}
+
+/// Speaks hyprctl's protocol on `.socket.sock` directly, saving a process
+/// spawn per call. Each request gets its own connection: write
+/// `[flags]/command args`, then read until Hyprland hangs up.
+struct Socket {
+    path: PathBuf,
+}
+
+impl Socket {
+    fn locate() -> Option<Socket> {
+        socket_path(".socket.sock").map(|path| Socket { path })
+    }
+}
+
+/// Flags `socket_request` knows how to put on the wire.
+const FLAGS: [&str; 2] = ["-j", "-r"];
+
+/// hyprctl arguments as a socket request: `-j clients` is `j/clients`. Only
+/// known flags ahead of the command count as flags; everything from the
+/// first word on is passed as-is, so a dispatch argument like
+/// `-1,address:0x1` stays where it is.
+fn socket_request(args: &[&str]) -> String {
+    if let ["--batch", commands] = args {
+        return format!("[[BATCH]]{}", commands);
+    }
+    let command = args
+        .iter()
+        .position(|a| !FLAGS.contains(a))
+        .unwrap_or(args.len());
+    let (flags, words) = args.split_at(command);
+    let flags: String = flags.iter().map(|f| f.trim_start_matches('-')).collect();
+    match flags.is_empty() {
+        true => words.join(" "),
+        false => format!("{}/{}", flags, words.join(" ")),
+    }
+}
+
+/// Where this Hyprland instance keeps socket `name`: under
+/// `$XDG_RUNTIME_DIR/hypr` since 0.40, under `/tmp/hypr` before that.
+fn socket_path(name: &str) -> Option<PathBuf> {
+    let signature = env::var_os("HYPRLAND_INSTANCE_SIGNATURE").filter(|s| !s.is_empty())?;
+    let runtime = env::var_os("XDG_RUNTIME_DIR")
+        .filter(|d| !d.is_empty())
+        .map(|d| PathBuf::from(d).join("hypr"));
+    runtime
+        .into_iter()
+        .chain([PathBuf::from("/tmp/hypr")])
+        .map(|dir| dir.join(&signature).join(name))
+        .find(|path| path.exists())
+}
+
+impl Hyprctl for Socket {
+    fn run(&self, args: &[&str]) -> io::Result<Reply> {
+        let request = socket_request(args);
+        let mut stream = match UnixStream::connect(&self.path) {
+            Ok(stream) => stream,
+            Err(e) => {
+                crate::log_info(&format!(
+                    "hyprctl: socket {} unreachable, using the hyprctl binary — {}",
+                    self.path.display(),
+                    e
+                ));
+                return Cli.run(args);
+            }
+        };
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        stream.write_all(request.as_bytes())?;
+        let mut stdout = Vec::new();
+        stream.read_to_end(&mut stdout)?;
+        // Like the CLI, Hyprland reports rejected requests in the reply text
+        Ok(Reply {
+            success: true,
+            stdout,
+            stderr: Vec::new(),
+        })
+    }
+}
+
+/// Where the test fake keeps its canned replies and `dispatch.log`.
+#[cfg(test)]
+pub fn fake_dir() -> PathBuf {
+    env::temp_dir()
+        .join(format!("omaveil-test-{}", std::process::id()))
+        .join("hyprctl")
+}
+
+/// Canned responses for running without Hyprland; see the module comment.
+#[cfg(test)]
+struct Fake {
+    dir: PathBuf,
+}
+
+#[cfg(test)]
+impl Hyprctl for Fake {
+    fn run(&self, args: &[&str]) -> io::Result<Reply> {
+        let commands: Vec<String> = match args {
+            ["--batch", commands] => commands.split(';').map(|c| c.trim().to_string()).collect(),
+            ["dispatch", ..] => vec![args.join(" ")],
+            _ => Vec::new(),
+        };
+        if !commands.is_empty() {
+            let mut log = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.dir.join("dispatch.log"))?;
+            for command in &commands {
+                writeln!(log, "{}", command)?;
+            }
+            return Ok(Reply {
+                success: true,
+                stdout: vec!["ok"; commands.len()].join("\n\n").into_bytes(),
+                stderr: Vec::new(),
+            });
+        }
+
+        let query = args
+            .iter()
+            .find(|a| !FLAGS.contains(a))
+            .copied()
+            .unwrap_or_default();
+        match fs::read(self.dir.join(format!("{}.json", query))) {
+            Ok(stdout) => Ok(Reply {
+                success: true,
+                stdout,
+                stderr: Vec::new(),
+            }),
+            Err(e) => Ok(Reply {
+                success: false,
+                stdout: Vec::new(),
+                stderr: format!("fake hyprctl: no canned reply for {} — {}", query, e).into_bytes(),
+            }),
+        }
+    }
+}
+
+static BACKEND: OnceLock<Box<dyn Hyprctl>> = OnceLock::new();
+
+fn backend() -> &'static dyn Hyprctl {
+    BACKEND
+        .get_or_init(|| {
+            fake().unwrap_or_else(|| match Socket::locate() {
+                Some(socket) => Box::new(socket),
+                None => Box::new(Cli),
+            })
+        })
+        .as_ref()
+}
+
+/// The fake, under test only.
+#[cfg(test)]
+fn fake() -> Option<Box<dyn Hyprctl>> {
+    Some(Box::new(Fake { dir: fake_dir() }))
+}
+
+#[cfg(not(test))]
+fn fake() -> Option<Box<dyn Hyprctl>> {
+    None
+}
+
+/// Runs `hyprctl <args>` through whichever transport is active.
+pub fn hyprctl(args: &[&str]) -> io::Result<Reply> {
+    let mut reply = crate::timed(&format!("hyprctl {}", args.join(" ")), || {
+        backend().run(args)
+    })?;
+    if args.contains(&"-j") {
+        reply.stdout = strip_leading_noise(reply.stdout, args);
+    }
+    Ok(reply)
+}
+
+/// A Hyprland release, compared field by field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version(pub u64, pub u64, pub u64);
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "v{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+static VERSION: OnceLock<Option<Version>> = OnceLock::new();
+
+/// The running Hyprland's version from `hyprctl version -j`, asked at most
+/// once per run. `None` if it can't be determined (no Hyprland, or a `tag`
+/// that isn't `vX.Y.Z`).
+pub fn version() -> Option<Version> {
+    *VERSION.get_or_init(|| {
+        let reply = hyprctl(&["-j", "version"]).ok().filter(|r| r.success)?;
+        let reply: VersionReply = serde_json::from_slice(&reply.stdout).ok()?;
+        parse_version(&reply.tag)
+    })
+}
+
+#[derive(Deserialize)]
+struct VersionReply {
+    tag: String,
+}
+
+// "v0.41.2" or "v0.41.2-b" (git builds) -> Version(0, 41, 2)
+fn parse_version(tag: &str) -> Option<Version> {
+    let core = tag.trim_start_matches('v').split('-').next()?;
+    let mut parts = core.split('.').map(|p| p.parse::<u64>().ok());
+    let major = parts.next()??;
+    let minor = parts.next()??;
+    let patch = parts.next().flatten().unwrap_or(0);
+    Some(Version(major, minor, patch))
+}
+
+/// The dispatcher to send for `name` on the running Hyprland, covering the
+/// ones that only exist in newer releases. Only consults the version for
+/// names that need it.
+pub fn dispatcher(name: &'static str) -> &'static str {
+    match name {
+        // Added in 0.38. Callers only ask when the window is in the other
+        // state, so toggling does the same thing
+        "setfloating" | "settiled" if version().is_some_and(|v| v < Version(0, 38, 0)) => {
+            "togglefloating"
+        }
+        _ => name,
+    }
+}
+
+/// Sends several hyprctl commands (`dispatch focuswindow address:0x1`, ...)
+/// as one batched request. The reply holds one answer per command.
+pub fn batch(commands: &[String]) -> io::Result<Reply> {
+    hyprctl(&["--batch", &commands.join(" ; ")])
+}
+
+/// Some hyprctl builds and plugins print warnings to stdout ahead of the
+/// JSON. If the output doesn't parse as-is, drop whole lines from the top
+/// until what's left does; if nothing does, return it untouched so the
+/// caller reports the real parse error.
+fn strip_leading_noise(stdout: Vec<u8>, args: &[&str]) -> Vec<u8> {
+    let text = String::from_utf8_lossy(&stdout);
+    let parses = |text: &str| serde_json::from_str::<IgnoredAny>(text).is_ok();
+    if parses(&text) {
+        return stdout;
+    }
+
+    let line_starts = text.match_indices('\n').map(|(i, _)| i + 1);
+    for start in line_starts {
+        let rest = &text[start..];
+        if rest.trim_start().starts_with(['{', '[']) && parses(rest) {
+            crate::log_info(&format!(
+                "hyprctl {}: ignored leading non-JSON output — {}",
+                args.join(" "),
+                text[..start].trim().replace('\n', " | ")
+            ));
+            return rest.as_bytes().to_vec();
+        }
+    }
+    stdout
+}
+
+/// Hyprland's event stream, one `event>>data` line per event.
+#[cfg(not(test))]
+pub fn events() -> io::Result<Box<dyn BufRead + Send>> {
+    let path = socket_path(".socket2.sock").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "Hyprland's event socket not found; is HYPRLAND_INSTANCE_SIGNATURE set?",
+        )
+    })?;
+    Ok(Box::new(BufReader::new(UnixStream::connect(path)?)))
+}
+
+/// Under test, the lines of the fake's `events` file.
+#[cfg(test)]
+pub fn events() -> io::Result<Box<dyn BufRead + Send>> {
+    let file = fs::File::open(fake_dir().join("events"))?;
+    Ok(Box::new(BufReader::new(file)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn socket_request_only_takes_leading_flags() {
+        assert_eq!(socket_request(&["-j", "clients"]), "j/clients");
+        assert_eq!(socket_request(&["version"]), "version");
+        assert_eq!(
+            socket_request(&["dispatch", "movetoworkspace", "-1,address:0xa1"]),
+            "dispatch movetoworkspace -1,address:0xa1"
+        );
+        assert_eq!(
+            socket_request(&["dispatch", "movewindowpixel", "exact -20 40,address:0xa1"]),
+            "dispatch movewindowpixel exact -20 40,address:0xa1"
+        );
+        assert_eq!(
+            socket_request(&["--batch", "dispatch focuswindow address:0xa1"]),
+            "[[BATCH]]dispatch focuswindow address:0xa1"
+        );
+    }
+}
@@ -0,0 +1,30 @@
+// JSON on top of serde_json. Data with a fixed shape (the state file,
+// Hyprland's replies) goes through derived structs; `Value` is for the
+// loosely shaped rest (other compositors' IPC, `serve` requests), with the
+// shortcuts its readers share in `ValueExt`.
+
+pub use serde_json::Value;
+
+pub trait ValueExt {
+    /// Convenience for `obj.get(key).and_then(as_str)`, defaulting to "".
+    fn str_field(&self, key: &str) -> &str;
+
+    /// The elements of an array; empty for anything else.
+    fn items(&self) -> &[Value];
+}
+
+impl ValueExt for Value {
+    fn str_field(&self, key: &str) -> &str {
+        self.get(key).and_then(Value::as_str).unwrap_or("")
+    }
+
+    fn items(&self) -> &[Value] {
+        self.as_array().map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Escapes `text` for use inside a JSON string literal (without the quotes).
+pub fn escape(text: &str) -> String {
+    let quoted = Value::from(text).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
@@ -10,19 +10,54 @@
  * making it a natural fit for Omarchy setups where Walker is already present.
 */
 
+mod backend;
+mod config;
+mod hypr;
+mod json;
+mod niri;
+mod pattern;
+mod river;
+mod sway;
+mod toplevel;
+
+use backend::backend;
+use config::{Config, CycleOrder, LogLevel};
+use hypr::hyprctl;
+use json::ValueExt;
+use pattern::Pattern;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     env,
     fs::{self, OpenOptions},
-    io::{self, Write},
-    path::Path,
-    process::{Command, Stdio},
+    io::{self, BufRead, IsTerminal, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    process::{self, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Mutex, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 const CACHE_DIR: &str = "/tmp/minimize-state";
-const CACHE_FILE: &str = "/tmp/minimize-state/windows.json";
 const PREVIEW_DIR: &str = "/tmp/window-previews";
 const LOG_FILE: &str = "/tmp/omaveil.log";
+// How long `minimize_visible` leaves the special workspace up so the move
+// animation can play before hiding it
+const VISIBLE_MINIMIZE_MS: u64 = 300;
+// How often `watch` looks for windows to minimize for being idle
+const IDLE_CHECK: Duration = Duration::from_secs(30);
+// How long to wait for another omaveil to finish with the state file
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+// How long to wait before trusting an empty client list enough to archive
+const ARCHIVE_RECHECK: Duration = Duration::from_secs(1);
+// Largest thumbnail `preview_embed` will inline into the state file
+const PREVIEW_EMBED_MAX: usize = 64 * 1024;
+// errno for "Invalid cross-device link", returned when rename spans filesystems
+const EXDEV: i32 = 18;
 const ICONS: [(&str, &str); 10] = [
     ("firefox", ""),
     ("alacritty", ""),
@@ -36,28 +71,356 @@ const ICONS: [(&str, &str); 10] = [
     ("default", "󰖲"),
 ];
 
-// Append a timestamped error line to /tmp/omaveil.log
-fn log_error(msg: &str) {
-    let timestamp = Command::new("date")
-        .arg("+%Y-%m-%d %H:%M:%S")
+static CONFIG: OnceLock<Config> = OnceLock::new();
+static PATHS: OnceLock<Paths> = OnceLock::new();
+static VERBOSITY: OnceLock<Verbosity> = OnceLock::new();
+
+fn config() -> &'static Config {
+    CONFIG.get_or_init(|| Config::load(&[]).unwrap_or_default())
+}
+
+/// How chatty stdout is, from the global `-q/--quiet` and `-v/--verbose`
+/// flags. Output that is the point of a command (`list`, `status`,
+/// `--print-geometry`, ...) is printed regardless.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Verbosity {
+    /// No summaries and no desktop notifications.
+    Quiet,
+    Normal,
+    /// Adds per-window progress for bulk operations.
+    Verbose,
+}
+
+fn verbosity() -> Verbosity {
+    VERBOSITY.get().copied().unwrap_or(Verbosity::Normal)
+}
+
+/// Prints a summary line like "Closed 3 window(s)"; silenced by `--quiet`.
+fn say(message: &str) {
+    if verbosity() >= Verbosity::Normal {
+        println!("{}", message);
+    }
+}
+
+/// Prints a progress line for bulk operations; only shown with `--verbose`.
+fn progress(message: &str) {
+    if verbosity() >= Verbosity::Verbose {
+        println!("{}", message);
+    }
+}
+
+/// Pulls the global verbosity flags out of `argv`, wherever they appear.
+fn take_verbosity(argv: &mut Vec<String>) -> io::Result<Verbosity> {
+    let before = argv.len();
+    argv.retain(|a| a != "-q" && a != "--quiet");
+    let quiet = argv.len() != before;
+    let before = argv.len();
+    argv.retain(|a| a != "-v" && a != "--verbose");
+    let verbose = argv.len() != before;
+    match (quiet, verbose) {
+        (true, true) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--quiet and --verbose can't be combined",
+        )),
+        (true, false) => Ok(Verbosity::Quiet),
+        (false, true) => Ok(Verbosity::Verbose),
+        (false, false) => Ok(Verbosity::Normal),
+    }
+}
+
+/// Where state, previews and the log live for this run. Normally under /tmp;
+/// if that isn't writable (read-only mount, full disk) everything moves to
+/// `$XDG_RUNTIME_DIR/omaveil` instead. State and previews get a subdirectory
+/// per Hyprland instance so two sessions never share a stack.
+struct Paths {
+    cache_dir: PathBuf,
+    cache_file: PathBuf,
+    /// Present while something needs the user's attention; holds a one-line reason
+    warning_file: PathBuf,
+    /// The entry of the last restored window, for `undo`
+    last_restored_file: PathBuf,
+    /// Address of the window `cycle` is currently showing
+    cycle_file: PathBuf,
+    /// Addresses the last `minimize-others` hid, one per line
+    others_file: PathBuf,
+    /// Locked (flock) while a command rewrites the state file
+    lock_file: PathBuf,
+    preview_dir: PathBuf,
+    log_file: PathBuf,
+}
+
+impl Paths {
+    fn new(cache_dir: PathBuf, preview_dir: PathBuf, log_file: PathBuf) -> Paths {
+        Paths {
+            cache_file: cache_dir.join("windows.json"),
+            warning_file: cache_dir.join("warning"),
+            last_restored_file: cache_dir.join("last-restored.json"),
+            cycle_file: cache_dir.join("cycle"),
+            others_file: cache_dir.join("others"),
+            lock_file: cache_dir.join("windows.lock"),
+            cache_dir,
+            preview_dir,
+            log_file,
+        }
+    }
+
+    /// Creates the directories and proves we can actually write into them.
+    fn ensure_writable(&self) -> io::Result<()> {
+        for dir in [&self.cache_dir, &self.preview_dir] {
+            fs::create_dir_all(dir)?;
+            let probe = dir.join(format!(".write-test.{}", process::id()));
+            fs::write(&probe, b"ok")?;
+            fs::remove_file(&probe)?;
+        }
+        Ok(())
+    }
+}
+
+/// Picks the first usable location for state, or explains why none is.
+fn resolve_paths() -> Result<Paths, String> {
+    let instance = instance_dir();
+    let mut candidates = vec![Paths::new(
+        PathBuf::from(CACHE_DIR).join(&instance),
+        PathBuf::from(PREVIEW_DIR).join(&instance),
+        PathBuf::from(LOG_FILE),
+    )];
+    if let Some(runtime) = env::var_os("XDG_RUNTIME_DIR").filter(|v| !v.is_empty()) {
+        let base = PathBuf::from(runtime).join("omaveil");
+        candidates.push(Paths::new(
+            base.join("state").join(&instance),
+            base.join("previews").join(&instance),
+            base.join("omaveil.log"),
+        ));
+    }
+
+    let mut failures = Vec::new();
+    for candidate in candidates {
+        match candidate.ensure_writable() {
+            Ok(()) => return Ok(candidate),
+            Err(e) => failures.push(format!("{} ({})", candidate.cache_dir.display(), e)),
+        }
+    }
+    Err(format!(
+        "no writable state directory (is /tmp read-only or full?); tried {}",
+        failures.join(", ")
+    ))
+}
+
+/// Subdirectory for this Hyprland instance: its signature, reduced to a
+/// safe alphabet. Empty (no subdirectory) outside Hyprland.
+fn instance_dir() -> String {
+    env::var("HYPRLAND_INSTANCE_SIGNATURE")
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+        .collect()
+}
+
+/// State from before it was kept per instance sits one level up. The first
+/// instance to run takes it over; entries for another instance's windows
+/// are pruned as closed.
+fn adopt_unscoped_state() {
+    let cache_file = &paths().cache_file;
+    let Some(parent) = paths().cache_dir.parent() else {
+        return;
+    };
+    let legacy = parent.join("windows.json");
+    if instance_dir().is_empty() || cache_file.exists() || !legacy.is_file() {
+        return;
+    }
+    let Ok(_lock) = lock_state() else {
+        return;
+    };
+    if !cache_file.exists() && fs::rename(&legacy, cache_file).is_ok() {
+        log_info(&format!(
+            "state: moved {} to {} (state is now per Hyprland instance)",
+            legacy.display(),
+            cache_file.display()
+        ));
+    }
+}
+
+/// After a compositor restart every stored address is dead, and the picker
+/// would offer nothing but ghosts. When the state has entries and not one
+/// of them belongs to a live window, it is set aside as
+/// `windows-<date>.json` next to the state file and OmaVeil starts empty.
+/// Runs when `watch` starts and on `reconcile`. Does nothing if the
+/// compositor can't be asked, and an empty client list (Hyprland still
+/// starting, a query that raced a reload) only counts once a second look
+/// after `ARCHIVE_RECHECK` agrees.
+fn archive_stale_state() -> io::Result<()> {
+    let _lock = lock_state()?;
+    let windows = load_state()?;
+    if windows.is_empty() {
+        return Ok(());
+    }
+    let Some(mut live) = live_addresses() else {
+        return Ok(());
+    };
+    if live.is_empty() {
+        thread::sleep(ARCHIVE_RECHECK);
+        let Some(again) = live_addresses() else {
+            return Ok(());
+        };
+        live = again;
+    }
+    if windows.iter().any(|w| live.contains(&w.address)) {
+        return Ok(());
+    }
+
+    let stamp = local_time("%Y%m%d-%H%M%S").unwrap_or_else(|| "stale".to_string());
+    let archive = paths().cache_dir.join(format!("windows-{}.json", stamp));
+    fs::rename(&paths().cache_file, &archive)?;
+    log_info(&format!(
+        "state: none of the {} entry(s) has a live window (compositor restarted?), archived to {}",
+        windows.len(),
+        archive.display()
+    ));
+    Ok(())
+}
+
+fn paths() -> &'static Paths {
+    PATHS.get().expect("state paths are resolved at startup")
+}
+
+// Append a timestamped error line to the log (/tmp/omaveil.log by default)
+pub(crate) fn log_error(msg: &str) {
+    log_line(LogLevel::Error, msg);
+}
+
+// Append a timestamped line for housekeeping actions worth tracing later
+// (e.g. which entries `prune` restored or dropped)
+pub(crate) fn log_info(msg: &str) {
+    log_line(LogLevel::Info, msg);
+}
+
+fn log_enabled(level: LogLevel) -> bool {
+    // Config loading logs its own errors, so don't force it from here
+    CONFIG
+        .get()
+        .map_or(level <= LogLevel::Info, |c| level <= c.log_level)
+}
+
+/// Runs `f` and, at `log_level = "debug"`, logs how long it took. Used around
+/// every external command so slow minimizes can be pinned on hyprctl, grim,
+/// convert or the picker.
+pub(crate) fn timed<T>(what: &str, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    if log_enabled(LogLevel::Debug) {
+        log_line(
+            LogLevel::Debug,
+            &format!("timing: {} took {} ms", what, start.elapsed().as_millis()),
+        );
+    }
+    result
+}
+
+/// Tags every log line from this process so the lines of one invocation
+/// can be told apart from the next: start time (unix seconds) and pid.
+fn invocation_id() -> &'static str {
+    static ID: OnceLock<String> = OnceLock::new();
+    ID.get_or_init(|| format!("{}-{}", unix_now(), process::id()))
+}
+
+/// The current local time in `date(1)` `format`, e.g. "%Y-%m-%d".
+fn local_time(format: &str) -> Option<String> {
+    let output = Command::new("date")
+        .arg(format!("+{}", format))
         .output()
-        .ok()
-        .and_then(|o| String::from_utf8(o.stdout).ok())
-        .unwrap_or_else(|| "?".to_string());
-    let timestamp = timestamp.trim();
-    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(LOG_FILE) {
-        let _ = writeln!(file, "[{}] ERROR: {}", timestamp, msg);
+        .ok()?;
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
+
+fn log_line(level: LogLevel, msg: &str) {
+    if !log_enabled(level) {
+        return;
+    }
+    let level = match level {
+        LogLevel::Error => "ERROR",
+        LogLevel::Info => "INFO",
+        LogLevel::Debug => "DEBUG",
+    };
+    let timestamp = local_time("%Y-%m-%d %H:%M:%S").unwrap_or_else(|| "?".to_string());
+    let log_file = PATHS
+        .get()
+        .map_or_else(|| PathBuf::from(LOG_FILE), |p| p.log_file.clone());
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(log_file) {
+        let _ = writeln!(
+            file,
+            "[{}] [{}] {}: {}",
+            timestamp,
+            invocation_id(),
+            level,
+            msg
+        );
     }
 }
 
-#[derive(Clone)]
+/// One entry in the state file. Fields an older version didn't write are
+/// left unset when reading.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 struct MinimizedWindow {
     address: String,
     display_title: String,
     class: String,
     original_title: String,
+    #[serde(rename = "preview", serialize_with = "preview_or_empty")]
     preview_path: Option<String>,
     icon: String,
+    /// The thumbnail itself, base64-encoded, when `preview_embed` is on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    preview_base64: Option<String>,
+    /// Workspace the window was minimized from, and its name, which still
+    /// finds a named workspace after it was recreated under a new id.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    workspace_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    workspace_name: Option<String>,
+    /// Tiled window that sat next to this one at minimize time, and which
+    /// side of it ("l", "r", "u", "d") this window was on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    neighbor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    neighbor_side: Option<String>,
+    /// Unix timestamp (seconds) of when the window was minimized.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    minimized_at: Option<u64>,
+    /// Named stash this window was minimized into, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+    /// Freeform note given with `minimize --note`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+    /// Unix timestamp (seconds) at which `minimize --ttl` brings the window
+    /// back by itself.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    restore_at: Option<u64>,
+    /// Where the window was at minimize time: floating or tiled, its rect,
+    /// and the monitor (with that monitor's transform) it was on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    floating: Option<bool>,
+    // Flat x/y/width/height keys, as the state file has always had them
+    #[serde(flatten)]
+    geometry: Option<Rect>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    monitor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transform: Option<i64>,
+    /// Workspace the window was hidden on (`special:minimum`, or a
+    /// per-class `special:minimum-firefox`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hidden_on: Option<String>,
+    /// Fullscreen or pinned at minimize time, re-applied on restore.
+    #[serde(skip_serializing_if = "Option::is_none", deserialize_with = "lenient")]
+    fullscreen: Option<Fullscreen>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pinned: bool,
+    /// Brought back with `restore --keep`: the entry stays in the state file
+    /// but is ignored until the window is minimized again.
+    #[serde(rename = "state", with = "entry_state")]
+    restored: bool,
 }
 
 fn get_app_icon(class_name: &str) -> String {
@@ -70,397 +433,5534 @@ fn get_app_icon(class_name: &str) -> String {
         .to_string()
 }
 
-fn capture_window_preview(window_id: &str, geometry: &str) -> io::Result<String> {
-    let preview_path = format!("{}/{}.png", PREVIEW_DIR, window_id);
-    let thumb_path = format!("{}/{}.thumb.png", PREVIEW_DIR, window_id);
-
-    Command::new("grim")
-        .args(["-g", geometry, &preview_path])
-        .output()?;
-
-    Command::new("convert")
-        .args([
-            &preview_path,
-            "-resize",
-            "200x150^",
-            "-gravity",
-            "center",
-            "-extent",
-            "200x150",
-            &thumb_path,
-        ])
-        .output()?;
-
-    fs::remove_file(&preview_path)?;
+/// Shortens `title` to at most `max` characters, ending in `…`. The cut
+/// moves back past combining marks, joiners and variation selectors so an
+/// accented letter or emoji sequence is dropped whole rather than split.
+fn truncate_title(title: &str, max: usize) -> String {
+    let chars: Vec<char> = title.chars().collect();
+    if max == 0 || chars.len() <= max {
+        return title.to_string();
+    }
+    let extends = |c: char| {
+        matches!(c,
+            '\u{0300}'..='\u{036F}'
+            | '\u{1AB0}'..='\u{1AFF}'
+            | '\u{20D0}'..='\u{20FF}'
+            | '\u{200D}'
+            | '\u{FE00}'..='\u{FE0F}'
+            | '\u{1F3FB}'..='\u{1F3FF}')
+    };
+    let mut cut = max.saturating_sub(1);
+    while cut > 0 && (extends(chars[cut]) || chars[cut - 1] == '\u{200D}') {
+        cut -= 1;
+    }
+    let mut short: String = chars[..cut].iter().collect();
+    short.truncate(short.trim_end().len());
+    short.push('…');
+    short
+}
 
-    Ok(thumb_path)
+/// A rectangle in Hyprland's logical layout coordinates.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+struct Rect {
+    x: i64,
+    y: i64,
+    width: i64,
+    height: i64,
 }
 
-fn create_json_output(windows: &[MinimizedWindow]) -> String {
-    let mut output = String::from("[");
-    for (i, window) in windows.iter().enumerate() {
-        if i > 0 {
-            output.push(',');
-        }
-        output.push_str(&format!(
-            "{{\"address\":\"{}\",\"display_title\":\"{}\",\"class\":\"{}\",\"original_title\":\"{}\",\"preview\":\"{}\",\"icon\":\"{}\"}}",
-            window.address,
-            window.display_title.replace('"', "\\\""),
-            window.class,
-            window.original_title.replace('"', "\\\""),
-            window.preview_path.as_ref().unwrap_or(&String::new()),
-            window.icon
-        ));
+impl Rect {
+    fn contains(&self, x: i64, y: i64) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
     }
-    output.push(']');
-    output
-}
 
-fn parse_window_info(info: &str) -> io::Result<HashMap<String, String>> {
-    let mut result = HashMap::new();
-    let content = info.trim_matches(|c| c == '{' || c == '}');
+    fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        (right > x && bottom > y).then_some(Rect {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        })
+    }
 
-    for pair in content.split(',') {
-        if let Some((key, value)) = pair.split_once(':') {
-            let clean_key = key.trim().trim_matches('"');
-            let clean_value = value.trim().trim_matches('"');
-            result.insert(clean_key.to_string(), clean_value.to_string());
-        }
+    fn area(&self) -> i64 {
+        self.width * self.height
     }
 
-    Ok(result)
+    /// Geometry in the `X,Y WxH` form grim expects.
+    fn to_grim(self) -> String {
+        format!("{},{} {}x{}", self.x, self.y, self.width, self.height)
+    }
 }
 
-fn parse_windows_from_json(content: &str) -> io::Result<Vec<MinimizedWindow>> {
-    let mut windows = Vec::new();
-    let content = content.trim();
+struct Monitor {
+    name: String,
+    /// Workspace currently shown on this monitor.
+    active_workspace: i64,
+    /// wl_output transform (0-7); odd values are rotated 90°/270°.
+    transform: i64,
+    /// Logical rect: pixel size divided by scale, with width/height swapped
+    /// for 90°/270° transforms, matching the coordinates windows report.
+    rect: Rect,
+}
 
-    if content.starts_with('[') && content.ends_with(']') {
-        let content = &content[1..content.len() - 1];
-        for window_json in content.split("},").map(|s| s.trim_end_matches(']')) {
-            let window_json = window_json.trim_start_matches(',').trim();
-            if window_json.is_empty() {
-                continue;
-            }
+fn monitors() -> Vec<Monitor> {
+    backend().monitors()
+}
 
-            let mut window_data = parse_window_info(window_json)?;
-            windows.push(MinimizedWindow {
-                address: window_data.remove("address").unwrap_or_default(),
-                display_title: window_data.remove("display_title").unwrap_or_default(),
-                class: window_data.remove("class").unwrap_or_default(),
-                original_title: window_data.remove("original_title").unwrap_or_default(),
-                icon: window_data.remove("icon").unwrap_or_default(),
-                preview_path: Some(window_data.remove("preview").unwrap_or_default()),
-            });
-        }
-    }
+/// Picks the monitor a window "belongs" to for preview purposes. A window
+/// straddling several monitors is captured on the one it overlaps most;
+/// ties go to the monitor holding its top-left corner.
+fn monitor_for<'a>(window: &Rect, monitors: &'a [Monitor]) -> Option<&'a Monitor> {
+    monitors
+        .iter()
+        .filter_map(|m| {
+            window
+                .intersection(&m.rect)
+                .map(|overlap| (m, overlap.area()))
+        })
+        .max_by_key(|(m, area)| (*area, m.rect.contains(window.x, window.y)))
+        .map(|(m, _)| m)
+}
 
-    Ok(windows)
+/// The region to grab for a window's preview: the window clamped to its
+/// monitor, so grim never captures across an output boundary.
+fn preview_geometry(window: &Rect, monitors: &[Monitor]) -> Rect {
+    monitor_for(window, monitors)
+        .and_then(|m| window.intersection(&m.rect))
+        .unwrap_or(*window)
 }
 
-fn restore_specific_window(window_id: &str) -> io::Result<()> {
-    let output = Command::new("hyprctl")
-        .args(["activeworkspace", "-j"])
-        .output()?;
+fn capture_window_preview(
+    window_id: &str,
+    window: &Rect,
+    monitors: &[Monitor],
+    capture_delay_ms: u64,
+) -> io::Result<String> {
+    let preview_dir = &paths().preview_dir;
+    let preview_path = preview_dir
+        .join(format!("{}.png", window_id))
+        .to_string_lossy()
+        .into_owned();
+    let thumb_path = preview_dir
+        .join(format!("{}.thumb.png", window_id))
+        .to_string_lossy()
+        .into_owned();
+    let geometry = preview_geometry(window, monitors).to_grim();
 
-    if !output.status.success() {
-        log_error(&format!(
-            "restore: hyprctl activeworkspace failed for address={} — {}",
-            window_id,
-            String::from_utf8_lossy(&output.stderr).trim()
-        ));
-        return Ok(());
+    if capture_delay_ms > 0 {
+        thread::sleep(Duration::from_millis(capture_delay_ms));
     }
 
-    let workspace_info =
-        String::from_utf8(output.stdout).expect("cannot get workspace_info from stdout");
-    let workspace_data = parse_window_info(&workspace_info)?;
-    let current_ws = workspace_data
-        .get("id")
-        .and_then(|id| id.parse().ok())
-        .unwrap_or(1);
-
-    let move_cmd = format!("{},address:{}", current_ws, window_id);
-    let move_result = Command::new("hyprctl")
-        .args(["dispatch", "movetoworkspace", &move_cmd])
-        .output()?;
+    let grabbed = timed("grim", || {
+        Command::new("grim")
+            .args(["-g", &geometry, &preview_path])
+            .output()
+    })?;
+    if !grabbed.status.success() {
+        let _ = fs::remove_file(&preview_path);
+        return Err(io::Error::other(format!(
+            "grim failed — {}",
+            String::from_utf8_lossy(&grabbed.stderr).trim()
+        )));
+    }
 
-    if !move_result.status.success() {
-        log_error(&format!(
-            "restore: movetoworkspace failed for address={} — stdout={} stderr={}",
-            window_id,
-            String::from_utf8_lossy(&move_result.stdout).trim(),
-            String::from_utf8_lossy(&move_result.stderr).trim()
-        ));
+    let converted = timed("convert", || {
+        Command::new("convert")
+            .args([
+                &preview_path,
+                "-resize",
+                "200x150^",
+                "-gravity",
+                "center",
+                "-background",
+                &config().preview_background,
+                "-extent",
+                "200x150",
+                &thumb_path,
+            ])
+            .output()
+    });
+    fs::remove_file(&preview_path)?;
+    let converted = converted?;
+    // A thumbnail convert didn't finish would leave the entry pointing at
+    // nothing, or at half an image
+    if !converted.status.success() {
+        let _ = fs::remove_file(&thumb_path);
+        return Err(io::Error::other(format!(
+            "convert failed — {}",
+            String::from_utf8_lossy(&converted.stderr).trim()
+        )));
     }
 
-    let focus_result = Command::new("hyprctl")
-        .args(["dispatch", "focuswindow", &format!("address:{}", window_id)])
-        .output()?;
+    Ok(thumb_path)
+}
 
-    if !focus_result.status.success() {
-        log_error(&format!(
-            "restore: focuswindow failed for address={} — stdout={} stderr={}",
-            window_id,
-            String::from_utf8_lossy(&focus_result.stdout).trim(),
-            String::from_utf8_lossy(&focus_result.stderr).trim()
+/// Reads a thumbnail back for `preview_embed`. Thumbnails over
+/// `PREVIEW_EMBED_MAX` bytes are left as a path only, so a detailed window
+/// can't blow up the state file.
+fn embed_preview(path: &str) -> Option<String> {
+    if !config().preview_embed {
+        return None;
+    }
+    let bytes = fs::read(path).ok()?;
+    if bytes.len() > PREVIEW_EMBED_MAX {
+        log_info(&format!(
+            "preview: not embedding {} ({} bytes, limit {})",
+            path,
+            bytes.len(),
+            PREVIEW_EMBED_MAX
         ));
+        return None;
     }
+    Some(base64(&bytes))
+}
 
-    let content = fs::read_to_string(CACHE_FILE)?;
-    let windows = parse_windows_from_json(&content)?;
-    let updated_windows: Vec<MinimizedWindow> = windows
-        .into_iter()
-        .filter(|w| w.address != window_id)
-        .collect();
-    fs::write(CACHE_FILE, create_json_output(&updated_windows))?;
+/// Standard, padded base64.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
 
-    Ok(())
+fn window_to_json(window: &MinimizedWindow) -> String {
+    serde_json::to_string(window).expect("state entries always serialize")
+}
+
+fn create_json_output(windows: &[MinimizedWindow]) -> String {
+    serde_json::to_string(windows).expect("state entries always serialize")
+}
+
+// Entries have always carried a `preview` key, empty without a thumbnail
+fn preview_or_empty<S: Serializer>(path: &Option<String>, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(path.as_deref().unwrap_or_default())
 }
 
-fn restore_all_windows() -> io::Result<()> {
-    let content = fs::read_to_string(CACHE_FILE)?;
-    let windows = parse_windows_from_json(&content)?;
+/// `None` for a value this version doesn't know (a fullscreen kind added
+/// later, say) rather than losing the whole entry over it.
+fn lenient<'de, D: Deserializer<'de>, T: DeserializeOwned>(d: D) -> Result<Option<T>, D::Error> {
+    Ok(serde_json::from_value(json::Value::deserialize(d)?).ok())
+}
+
+/// `restored` is written as `"state": "restored"` or `"minimized"`.
+mod entry_state {
+    use serde::{Deserialize, Deserializer, Serializer};
 
-    for window in windows {
-        restore_specific_window(&window.address)?;
+    pub fn serialize<S: Serializer>(restored: &bool, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(if *restored { "restored" } else { "minimized" })
     }
 
-    Ok(())
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<bool, D::Error> {
+        Ok(String::deserialize(d)? == "restored")
+    }
 }
 
-/// Opens a Walker dmenu picker listing all minimized windows.
-/// Uses index mode (-i) so we get back the 0-based position of the selection,
-/// avoiding any text-mangling issues (e.g. walker stripping leading icon chars).
-fn show_restore_menu() -> io::Result<()> {
-    if !Path::new(CACHE_FILE).exists() {
-        return Ok(());
-    }
+/// Every key the state file has ever used, for reading the legacy format.
+const STATE_KEYS: [&str; 26] = [
+    "address",
+    "display_title",
+    "class",
+    "original_title",
+    "preview",
+    "icon",
+    "preview_base64",
+    "workspace_id",
+    "workspace_name",
+    "neighbor",
+    "neighbor_side",
+    "minimized_at",
+    "group",
+    "note",
+    "restore_at",
+    "floating",
+    "x",
+    "y",
+    "width",
+    "height",
+    "monitor",
+    "transform",
+    "hidden_on",
+    "fullscreen",
+    "pinned",
+    "state",
+];
 
-    let content = fs::read_to_string(CACHE_FILE)?;
-    let windows = parse_windows_from_json(&content)?;
+/// Reads a state file's worth of entries. Fields an older version didn't
+/// write are left unset.
+fn parse_windows_from_json(content: &str) -> io::Result<Vec<MinimizedWindow>> {
+    Ok(parse_state(content)?.0)
+}
 
-    if windows.is_empty() {
-        return Ok(());
+/// Like `parse_windows_from_json`, and also says whether the content was in
+/// the legacy format (see `legacy_entries`) and so should be written back.
+fn parse_state(content: &str) -> io::Result<(Vec<MinimizedWindow>, bool)> {
+    let content = content.trim();
+    if let Ok(entries) = serde_json::from_str::<Vec<json::Value>>(content) {
+        return Ok((
+            entries.into_iter().filter_map(window_from_json).collect(),
+            false,
+        ));
+    }
+    match content.strip_prefix('[').and_then(|c| c.strip_suffix(']')) {
+        Some(inner) => Ok((legacy_entries(inner), true)),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "expected an array of windows",
+        )),
     }
+}
+
+/// Entries as versions before proper escaping wrote them: flat objects in
+/// which only `"` was escaped, so a backslash in a title (one at the very
+/// end, say) makes the file invalid JSON. Each entry is recovered on its own
+/// by cutting it at the keys the state file uses; one without an address is
+/// skipped rather than taking the rest of the stack with it.
+fn legacy_entries(inner: &str) -> Vec<MinimizedWindow> {
+    inner
+        .split("},{\"address\":")
+        .enumerate()
+        .filter_map(|(i, chunk)| {
+            let chunk = chunk.trim().trim_start_matches('{').trim_end_matches('}');
+            let chunk = match i {
+                0 => chunk.to_string(),
+                _ => format!("\"address\":{}", chunk),
+            };
+            let window = window_from_json(legacy_entry(&chunk));
+            if window.as_ref().is_none_or(|w| w.address.is_empty()) {
+                log_error(&format!(
+                    "state: skipped an unreadable entry — {}",
+                    chunk.chars().take(80).collect::<String>()
+                ));
+                return None;
+            }
+            window
+        })
+        .collect()
+}
 
-    let input = windows
+/// One legacy entry's `"key":value,...` text as an object. A value that
+/// doesn't parse is left out.
+fn legacy_entry(text: &str) -> json::Value {
+    let mut starts: Vec<(usize, &str)> = STATE_KEYS
         .iter()
-        .map(|w| format!("{} - {}", w.class, w.original_title))
-        .collect::<Vec<_>>()
-        .join("\n");
-
-    let mut child = Command::new("walker")
-        .args(["-d", "-i", "-p", "Restore window:"])
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()
-        .map_err(|e| {
-            log_error(&format!("restore: failed to spawn walker — {}", e));
-            e
-        })?;
+        .filter_map(|key| {
+            let pattern = format!("\"{}\":", key);
+            text.match_indices(&pattern)
+                .map(|(at, _)| at)
+                .find(|&at| at == 0 || text[..at].ends_with(','))
+                .map(|at| (at, *key))
+        })
+        .collect();
+    starts.sort();
 
-    if let Some(mut stdin) = child.stdin.take() {
-        stdin.write_all(input.as_bytes()).map_err(|e| {
-            log_error(&format!("restore: failed to write to walker stdin — {}", e));
-            e
-        })?;
-    }
+    let fields = starts
+        .iter()
+        .enumerate()
+        .filter_map(|(i, &(at, key))| {
+            let value_start = at + key.len() + 3;
+            let value_end = starts.get(i + 1).map_or(text.len(), |&(next, _)| next - 1);
+            let raw = text.get(value_start..value_end).unwrap_or_default().trim();
+            let value = match raw {
+                _ if raw.len() >= 2 && raw.starts_with('"') && raw.ends_with('"') => {
+                    json::Value::String(raw[1..raw.len() - 1].replace("\\\"", "\""))
+                }
+                _ => serde_json::from_str(raw).ok()?,
+            };
+            Some((key.to_string(), value))
+        })
+        .collect();
+    json::Value::Object(fields)
+}
 
-    let output = child.wait_with_output().map_err(|e| {
-        log_error(&format!("restore: walker wait_with_output failed — {}", e));
-        e
-    })?;
+/// The entry as a window, or `None` (logged) if it isn't shaped like one.
+fn window_from_json(entry: json::Value) -> Option<MinimizedWindow> {
+    serde_json::from_value(entry)
+        .inspect_err(|e| log_error(&format!("state: skipped an unreadable entry — {}", e)))
+        .ok()
+}
 
-    let raw = String::from_utf8_lossy(&output.stdout).trim().to_string();
+/// Entries for windows that are currently minimized.
+fn load_minimized_windows() -> io::Result<Vec<MinimizedWindow>> {
+    Ok(load_state()?.into_iter().filter(|w| !w.restored).collect())
+}
 
-    if raw.is_empty() {
-        return Ok(());
+/// Every entry in the state file, including ones kept by `restore --keep`.
+/// Anything that rewrites the state file must start from this.
+fn load_state() -> io::Result<Vec<MinimizedWindow>> {
+    let cache_file = &paths().cache_file;
+    if !cache_file.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(cache_file)?;
+    if let Ok((mut windows, false)) = parse_state(&content) {
+        drop_duplicates(&mut windows);
+        return Ok(windows);
     }
 
-    match raw.parse::<usize>() {
-        Ok(idx) if idx < windows.len() => {
-            restore_specific_window(&windows[idx].address)?;
-        }
-        Ok(idx) => {
-            log_error(&format!(
-                "restore: walker returned index {} but only {} windows are minimized",
-                idx,
+    // The file needs rewriting; look again under the lock, since whoever
+    // held it may have done that already
+    let _lock = lock_state()?;
+    if !cache_file.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(cache_file)?;
+    let trimmed = content.trim();
+
+    // A truncated file (crash mid-write, `: > windows.json`) just means an
+    // empty stack; put a well-formed one back
+    if trimmed.is_empty() {
+        write_atomic(cache_file, "[]")?;
+        return Ok(Vec::new());
+    }
+
+    let mut windows = match parse_state(trimmed) {
+        Ok((windows, false)) => windows,
+        Ok((windows, true)) => {
+            save_minimized_windows(&windows)?;
+            log_info(&format!(
+                "state: rewrote {} ({} entry(s)) from the legacy format",
+                cache_file.display(),
                 windows.len()
             ));
+            windows
         }
         Err(e) => {
+            // Keep the broken file around for inspection and start over
+            let backup = cache_file.with_extension("json.corrupt");
+            fs::rename(cache_file, &backup)?;
+            write_atomic(cache_file, "[]")?;
             log_error(&format!(
-                "restore: could not parse walker output {:?} as index — {}",
-                raw, e
+                "state: {} was corrupt ({}), moved it to {} and started a new one",
+                cache_file.display(),
+                e,
+                backup.display()
             ));
+            set_warning("State file was corrupt and has been reset");
+            return Ok(Vec::new());
         }
+    };
+    drop_duplicates(&mut windows);
+    Ok(windows)
+}
+
+/// Keeps only the newest entry per address. Older versions could record a
+/// window twice; the extra entries go with the next write.
+fn drop_duplicates(windows: &mut Vec<MinimizedWindow>) {
+    let mut seen = HashSet::new();
+    let before = windows.len();
+    windows.reverse();
+    windows.retain(|w| seen.insert(w.address.clone()));
+    windows.reverse();
+    if windows.len() < before {
+        log_info(&format!(
+            "state: dropped {} duplicate entr(ies)",
+            before - windows.len()
+        ));
     }
+}
 
+fn save_minimized_windows(windows: &[MinimizedWindow]) -> io::Result<()> {
+    write_atomic(&paths().cache_file, &create_json_output(windows))?;
+    // Everything is back; whatever went wrong before no longer matters
+    if windows.iter().all(|w| w.restored) {
+        let _ = fs::remove_file(&paths().warning_file);
+    }
     Ok(())
 }
 
-fn restore_window(window_id: Option<&str>) -> Result<(), io::Error> {
-    match window_id {
-        Some(id) => restore_specific_window(id),
-        None => show_restore_menu(),
+/// This process's hold on `lock_file`: how many `StateLock`s are alive, and
+/// the locked file while there are any. Counting makes the lock reentrant,
+/// so a function that takes it can call others that do.
+static STATE_LOCK: Mutex<(usize, Option<fs::File>)> = Mutex::new((0, None));
+
+/// Keeps other omaveil processes from rewriting the state file until
+/// dropped. Take one before `load_state` in anything that saves the state
+/// back, so two near-simultaneous commands (a keybind and a bar click) can't
+/// both read the old stack and each write back only their own change.
+struct StateLock;
+
+impl Drop for StateLock {
+    fn drop(&mut self) {
+        let mut held = STATE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        held.0 -= 1;
+        if held.0 == 0 {
+            // Closing the file releases the flock
+            held.1 = None;
+        }
     }
 }
 
-fn minimize_window() -> Result<(), io::Error> {
-    let output = Command::new("hyprctl")
-        .args(["activewindow", "-j"])
-        .output()?;
-
-    if !output.status.success() {
-        log_error(&format!(
-            "minimize: hyprctl activewindow failed — {}",
-            String::from_utf8_lossy(&output.stderr).trim()
-        ));
-        return Ok(());
+/// Takes the state lock, waiting up to `LOCK_TIMEOUT` for whoever holds it.
+fn lock_state() -> io::Result<StateLock> {
+    let mut held = STATE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if held.0 == 0 {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&paths().lock_file)?;
+        let started = Instant::now();
+        loop {
+            match file.try_lock() {
+                Ok(()) => break,
+                Err(fs::TryLockError::WouldBlock) if started.elapsed() < LOCK_TIMEOUT => {
+                    thread::sleep(Duration::from_millis(20));
+                }
+                Err(fs::TryLockError::WouldBlock) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!(
+                            "another omaveil has held {} for over {}s",
+                            paths().lock_file.display(),
+                            LOCK_TIMEOUT.as_secs()
+                        ),
+                    ));
+                }
+                Err(fs::TryLockError::Error(e)) => return Err(e),
+            }
+        }
+        if started.elapsed() >= Duration::from_millis(100) {
+            log_info(&format!(
+                "state: waited {}ms for another omaveil to finish",
+                started.elapsed().as_millis()
+            ));
+        }
+        held.1 = Some(file);
     }
+    held.0 += 1;
+    Ok(StateLock)
+}
 
-    let window_info =
-        String::from_utf8(output.stdout).expect("can't get window_info from output, stdout");
-    let window_data = parse_window_info(&window_info)?;
+/// Flags the Waybar indicator with the `warning` class until the stack is
+/// next emptied. Only the most recent reason is kept.
+fn set_warning(reason: &str) {
+    let _ = fs::write(&paths().warning_file, reason);
+}
 
-    // Don't minimize walker itself (it's the picker UI)
-    if window_data
-        .get("class")
-        .map_or(false, |c| c.to_lowercase() == "walker")
-    {
-        return Ok(());
-    }
+/// Replaces `path` with `contents` via a temp file + rename so a crash
+/// mid-write never leaves a truncated file behind. The temp file is synced
+/// before the rename, so a power cut can't leave the new name pointing at
+/// unwritten data. It lives next to the target so the rename stays on one
+/// filesystem; if it still fails with EXDEV we fall back to copying the temp
+/// file over the target.
+fn write_atomic(path: &Path, contents: &str) -> io::Result<()> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let tmp_path = dir.join(format!(".{}.{}.tmp", file_name, process::id()));
+    remove_stale_temp_files(dir, &file_name);
 
-    let window_addr = window_data
-        .get("address")
-        .ok_or("No address found")
-        .expect("error");
-    let short_addr: String = window_addr.chars().rev().take(4).collect();
-    let class_name = window_data
-        .get("class")
-        .ok_or("No class found")
-        .expect("error");
-    let title = window_data
-        .get("title")
-        .ok_or("No title found")
-        .expect("error");
-    let icon = get_app_icon(class_name);
-
-    let geometry = window_data.get("at").and_then(|at| {
-        window_data
-            .get("size")
-            .map(|size| format!("{},{}", at.trim(), size.trim()))
+    let written = fs::File::create(&tmp_path).and_then(|mut file| {
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()
     });
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
 
-    let preview_path = if let Some(geom) = geometry {
-        capture_window_preview(window_addr, &geom).ok()
-    } else {
-        None
-    };
+    match fs::rename(&tmp_path, path) {
+        Ok(()) => {
+            // Makes the rename itself durable; not every filesystem can
+            if let Ok(dir) = fs::File::open(dir) {
+                let _ = dir.sync_all();
+            }
+            Ok(())
+        }
+        Err(e) if e.raw_os_error() == Some(EXDEV) => {
+            log_error(&format!(
+                "state: rename {} -> {} crossed filesystems, falling back to copy",
+                tmp_path.display(),
+                path.display()
+            ));
+            let copied = fs::copy(&tmp_path, path).map(|_| ());
+            let _ = fs::remove_file(&tmp_path);
+            copied
+        }
+        Err(e) => {
+            log_error(&format!(
+                "state: rename {} -> {} failed — {}",
+                tmp_path.display(),
+                path.display(),
+                e
+            ));
+            let _ = fs::remove_file(&tmp_path);
+            Err(e)
+        }
+    }
+}
 
-    let window = MinimizedWindow {
-        address: window_addr.to_string(),
-        display_title: format!("{} {} - {} [{}]", icon, class_name, title, short_addr),
-        class: class_name.to_string(),
-        original_title: title.to_string(),
-        preview_path,
-        icon,
+/// Deletes temp files `write_atomic` left next to `file_name` when a process
+/// died between writing and renaming one. A temp file is named after the
+/// process that wrote it, so only those whose process is gone are touched.
+fn remove_stale_temp_files(dir: &Path, file_name: &str) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
     };
-
-    let dispatch_arg = format!("special:minimum,address:{}", window_addr);
-    let output = Command::new("hyprctl")
-        .args(["dispatch", "movetoworkspacesilent", &dispatch_arg])
-        .output()?;
-
-    if output.status.success() {
-        let content = fs::read_to_string(CACHE_FILE)?;
-        let mut windows = parse_windows_from_json(&content)?;
-        windows.push(window);
-        fs::write(CACHE_FILE, create_json_output(&windows))?;
-    } else {
-        log_error(&format!(
-            "minimize: movetoworkspacesilent failed for class={} address={} — stdout={} stderr={}",
-            class_name,
-            window_addr,
-            String::from_utf8_lossy(&output.stdout).trim(),
-            String::from_utf8_lossy(&output.stderr).trim()
-        ));
+    let prefix = format!(".{}.", file_name);
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some(pid) = name
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.strip_suffix(".tmp"))
+        else {
+            continue;
+        };
+        if pid.parse::<u32>().is_err() || Path::new("/proc").join(pid).exists() {
+            continue;
+        }
+        if fs::remove_file(entry.path()).is_ok() {
+            log_info(&format!(
+                "state: removed {} left behind by an interrupted write",
+                entry.path().display()
+            ));
+        }
     }
+}
 
-    Ok(())
+/// How a window filled its monitor.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+enum Fullscreen {
+    #[serde(rename = "maximized")]
+    Maximized,
+    #[serde(rename = "fullscreen")]
+    Full,
 }
 
-fn show_status() -> io::Result<()> {
-    if !Path::new(CACHE_FILE).exists() {
-        println!("{{\"text\":\"󰘸\",\"class\":\"empty\",\"tooltip\":\"No minimized windows\"}}");
-        return Ok(());
+impl Fullscreen {
+    fn as_str(self) -> &'static str {
+        match self {
+            Fullscreen::Maximized => "maximized",
+            Fullscreen::Full => "fullscreen",
+        }
     }
-    let content = fs::read_to_string(CACHE_FILE)?;
-    let windows = parse_windows_from_json(&content)?;
-    let count = windows.len();
+}
 
-    if count > 0 {
-        println!(
-            "{{\"text\":\"󰘸 {}\",\"class\":\"has-windows\",\"tooltip\":\"{} minimized windows\"}}",
-            count, count
-        );
-    } else {
-        println!("{{\"text\":\"󰘸\",\"class\":\"empty\",\"tooltip\":\"No minimized windows\"}}");
-    }
+/// A workspace to move a window to. Named workspaces go by name: Hyprland
+/// numbers them below zero, and a negative number in a move reads as
+/// relative ("one to the left") rather than as a workspace.
+#[derive(Clone, Debug, PartialEq)]
+enum WorkspaceTarget {
+    Number(i64),
+    Named(String),
+}
 
-    Ok(())
+/// A window as reported by `hyprctl clients -j` / `activewindow -j`.
+struct Client {
+    address: String,
+    class: String,
+    title: String,
+    workspace_id: i64,
+    workspace_name: String,
+    floating: bool,
+    rect: Option<Rect>,
+    fullscreen: Option<Fullscreen>,
+    pinned: bool,
+    /// Part of a tab group.
+    grouped: bool,
 }
 
-fn main() -> io::Result<()> {
-    fs::create_dir_all(CACHE_DIR)?;
-    fs::create_dir_all(PREVIEW_DIR)?;
+/// Every window Hyprland currently knows about, or `None` if
+/// `hyprctl clients -j` couldn't be queried (callers must not treat that as
+/// "no windows are alive").
+fn clients() -> Option<Vec<Client>> {
+    backend().clients()
+}
+
+fn live_addresses() -> Option<HashSet<String>> {
+    Some(clients()?.into_iter().map(|c| c.address).collect())
+}
 
-    if !Path::new(CACHE_FILE).exists() {
-        fs::write(CACHE_FILE, "[]")?;
+/// The direction back toward a window on `side` ("l", "r", "u" or "d").
+fn opposite(side: &str) -> &'static str {
+    match side {
+        "l" => "r",
+        "r" => "l",
+        "u" => "d",
+        _ => "u",
     }
+}
 
-    let args: Vec<String> = env::args().collect();
-    let command = args.get(1).map(|s| s.as_str()).unwrap_or("");
+/// Which side of `other` the `window` sits on ("l", "r", "u" or "d"), if the
+/// two share an edge region, along with the gap between them.
+fn side_of(window: &Rect, other: &Rect) -> Option<(&'static str, i64)> {
+    let overlaps_vertically =
+        window.y < other.y + other.height && other.y < window.y + window.height;
+    let overlaps_horizontally =
+        window.x < other.x + other.width && other.x < window.x + window.width;
 
-    match command {
-        "minimize" => {
-            minimize_window()?;
+    if overlaps_vertically {
+        if window.x >= other.x + other.width {
+            return Some(("r", window.x - (other.x + other.width)));
         }
-        "restore" => {
-            let window_id = args.get(2).map(|s| s.to_string());
-            restore_window(window_id.as_deref())?;
+        if other.x >= window.x + window.width {
+            return Some(("l", other.x - (window.x + window.width)));
         }
-        "restore-all" => {
-            restore_all_windows()?;
+    }
+    if overlaps_horizontally {
+        if window.y >= other.y + other.height {
+            return Some(("d", window.y - (other.y + other.height)));
         }
-        "restore-last" => {
-            if let Ok(content) = fs::read_to_string(CACHE_FILE) {
-                if let Ok(windows) = parse_windows_from_json(&content) {
-                    if let Some(window) = windows.last() {
-                        restore_window(Some(&window.address.clone()))?;
-                    }
-                }
+        if other.y >= window.y + window.height {
+            return Some(("u", other.y - (window.y + window.height)));
+        }
+    }
+    None
+}
+
+/// The closest tiled window sharing an edge with `window` on its workspace,
+/// used as the anchor for `restore --layout-hint`.
+fn find_neighbor(window: &Client, clients: &[Client]) -> Option<(String, &'static str)> {
+    let rect = window.rect?;
+    if window.floating {
+        return None;
+    }
+    clients
+        .iter()
+        .filter(|c| {
+            c.address != window.address && c.workspace_id == window.workspace_id && !c.floating
+        })
+        .filter_map(|c| side_of(&rect, &c.rect?).map(|(side, gap)| (c, side, gap)))
+        .min_by_key(|(_, _, gap)| *gap)
+        .map(|(c, side, _)| (c.address.clone(), side))
+}
+
+/// Runs a single `hyprctl dispatch`, logging any failure under `context`.
+fn dispatch(context: &str, dispatcher: &'static str, arg: &str) -> io::Result<bool> {
+    let dispatcher = hypr::dispatcher(dispatcher);
+    let result = hyprctl(&["dispatch", dispatcher, arg])?;
+    // hyprctl exits 0 even when Hyprland rejects the request; the reply says
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    let success = result.success && stdout.trim() == "ok";
+
+    if !success {
+        log_error(&format!(
+            "{}: {} {} failed on Hyprland {} — stdout={} stderr={}",
+            context,
+            dispatcher,
+            arg,
+            hypr::version().map_or("(unknown version)".to_string(), |v| v.to_string()),
+            stdout.trim(),
+            String::from_utf8_lossy(&result.stderr).trim()
+        ));
+    }
+    Ok(success)
+}
+
+/// Removes state entries whose window has been closed since it was minimized.
+/// Returns how many entries were dropped.
+fn prune_dead_windows() -> io::Result<usize> {
+    let _lock = lock_state()?;
+    let windows = load_state()?;
+    if windows.is_empty() {
+        return Ok(0);
+    }
+
+    let Some(live) = live_addresses() else {
+        return Ok(0);
+    };
+
+    let (alive, dead): (Vec<_>, Vec<_>) =
+        windows.into_iter().partition(|w| live.contains(&w.address));
+
+    if dead.is_empty() {
+        return Ok(0);
+    }
+
+    for window in &dead {
+        log_info(&format!(
+            "prune: dropped address={} class={} (window gone)",
+            window.address, window.class
+        ));
+    }
+    save_minimized_windows(&alive)?;
+    if alive.iter().any(|w| !w.restored) {
+        set_warning(&format!(
+            "{} minimized window(s) were closed and pruned",
+            dead.len()
+        ));
+    }
+
+    Ok(dead.len())
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Parses a duration like `90s`, `30m`, `2h` or `1d` (bare numbers are seconds).
+fn parse_duration(text: &str) -> io::Result<Duration> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let multiplier = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => 0,
+    };
+    match number.parse::<u64>() {
+        Ok(n) if multiplier > 0 => Ok(Duration::from_secs(n * multiplier)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "invalid duration {:?} (expected e.g. 90s, 30m, 2h, 1d)",
+                text
+            ),
+        )),
+    }
+}
+
+/// End-of-day housekeeping: drops entries whose window is gone and, with
+/// `restore`, sends every other matching window back to the workspace it was
+/// minimized from. `older_than` limits the restore to windows that have been
+/// minimized at least that long; dead entries are always dropped.
+fn prune(restore: bool, older_than: Option<Duration>) -> io::Result<()> {
+    let dropped = prune_dead_windows()?;
+    if !restore {
+        if dropped > 0 {
+            say(&format!("Dropped {} closed window(s)", dropped));
+        }
+        return Ok(());
+    }
+
+    let cutoff = older_than.map(|age| unix_now().saturating_sub(age.as_secs()));
+    let _lock = lock_state()?;
+    let windows = load_state()?;
+    let (due, keep): (Vec<_>, Vec<_>) = windows.into_iter().partition(|w| match cutoff {
+        _ if w.restored => false,
+        // Entries from before timestamps were recorded count as old enough
+        Some(cutoff) => w.minimized_at.is_none_or(|at| at <= cutoff),
+        None => true,
+    });
+
+    let mut remaining = keep;
+    let mut restored = 0;
+    for window in due {
+        let moved = match original_workspace(&window)? {
+            Some(workspace) => {
+                backend().move_to_workspace("prune", &window.address, &workspace, true)?
+            }
+            None => move_to_active_workspace(&window.address, true)?,
+        };
+        if moved {
+            restored += 1;
+            log_info(&format!(
+                "prune: restored address={} class={} to workspace {}",
+                window.address,
+                window.class,
+                window
+                    .workspace_id
+                    .map_or("(active)".to_string(), |id| id.to_string())
+            ));
+        } else {
+            remaining.push(window);
+        }
+    }
+    save_minimized_windows(&remaining)?;
+
+    say(&format!(
+        "Restored {} window(s), dropped {} closed window(s)",
+        restored, dropped
+    ));
+    Ok(())
+}
+
+/// Finds windows sitting on the hidden workspace without a state entry (lost to
+/// a state reset or moved there by hand), which OmaVeil otherwise can't
+/// bring back. They're either adopted into the stack with fresh metadata,
+/// or with `restore` sent to the active workspace.
+fn prune_orphans(restore: bool) -> io::Result<()> {
+    let Some(live) = clients() else {
+        return Err(io::Error::other(
+            "could not list Hyprland clients; see the log",
+        ));
+    };
+    let orphans = orphan_clients(&live)?;
+
+    if orphans.is_empty() {
+        say("No orphaned windows");
+        return Ok(());
+    }
+
+    if restore {
+        let mut restored = 0;
+        for client in &orphans {
+            if move_to_active_workspace(&client.address, true)? {
+                restored += 1;
+                log_info(&format!(
+                    "prune: restored orphan address={} class={}",
+                    client.address, client.class
+                ));
+            }
+        }
+        say(&format!("Restored {} orphaned window(s)", restored));
+        return Ok(());
+    }
+
+    let count = adopt_orphans(&orphans)?;
+    say(&format!(
+        "Added {} orphaned window(s) back to the stack",
+        count
+    ));
+    Ok(())
+}
+
+/// Brings the state back in line with Hyprland's client list after a crash
+/// or manual `hyprctl` moves: entries for closed windows are dropped,
+/// entries whose window was moved off the hidden workspace by hand are
+/// forgotten, and windows hidden there without an entry are adopted.
+fn reconcile() -> io::Result<()> {
+    let Some(live) = clients() else {
+        return Err(io::Error::other(
+            "could not list Hyprland clients; see the log",
+        ));
+    };
+    let by_address: HashMap<&str, &Client> = live.iter().map(|c| (c.address.as_str(), c)).collect();
+
+    let _lock = lock_state()?;
+    archive_stale_state()?;
+    let state = load_state()?;
+    let before = state.len();
+    let (mut closed, mut shown) = (0, 0);
+    let kept: Vec<MinimizedWindow> = state
+        .into_iter()
+        .filter(|w| match by_address.get(w.address.as_str()) {
+            None => {
+                closed += 1;
+                log_info(&format!(
+                    "reconcile: dropped address={} class={} (window gone)",
+                    w.address, w.class
+                ));
+                false
+            }
+            Some(client) if !w.restored && !backend().is_hidden(&client.workspace_name) => {
+                shown += 1;
+                log_info(&format!(
+                    "reconcile: dropped address={} class={} (now on workspace {})",
+                    w.address, w.class, client.workspace_name
+                ));
+                false
+            }
+            Some(_) => true,
+        })
+        .collect();
+    if kept.len() != before {
+        save_minimized_windows(&kept)?;
+    }
+
+    let orphans = orphan_clients(&live)?;
+    let adopted = if orphans.is_empty() {
+        0
+    } else {
+        adopt_orphans(&orphans)?
+    };
+
+    say(&format!(
+        "Dropped {} closed and {} no longer hidden window(s), adopted {} orphaned window(s)",
+        closed, shown, adopted
+    ));
+    Ok(())
+}
+
+/// Windows on the hidden workspace that have no state entry.
+fn orphan_clients(live: &[Client]) -> io::Result<Vec<&Client>> {
+    let known: HashSet<String> = load_state()?.into_iter().map(|w| w.address).collect();
+    Ok(live
+        .iter()
+        .filter(|c| backend().is_hidden(&c.workspace_name) && !known.contains(&c.address))
+        .collect())
+}
+
+/// Records orphaned windows as minimized, returning how many were added.
+fn adopt_orphans(orphans: &[&Client]) -> io::Result<usize> {
+    // The windows are already hidden, so there's nothing to take a preview of
+    let options = MinimizeOptions {
+        skip_preview: true,
+        ..Default::default()
+    };
+    let monitors = monitors();
+    let adopted: Vec<MinimizedWindow> = orphans
+        .iter()
+        .map(|client| {
+            let mut window = record_window(client, None, &monitors, &options);
+            // Where it came from is unknown; restore to the active workspace
+            window.workspace_id = None;
+            window.workspace_name = None;
+            log_info(&format!(
+                "prune: adopted orphan address={} class={}",
+                window.address, window.class
+            ));
+            window
+        })
+        .collect();
+    let count = adopted.len();
+    store_minimized(adopted)?;
+    Ok(count)
+}
+
+/// Starts a command in the background without waiting for it, so feedback
+/// hooks never slow down the action they accompany.
+fn spawn_detached(program: &str, args: &[&str]) -> io::Result<()> {
+    Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map(|_| ())
+}
+
+static SOUND_PLAYED: AtomicBool = AtomicBool::new(false);
+
+/// Plays a feedback sound, at most once per invocation so bulk commands like
+/// `restore-all` don't stack up a chorus. Quietly does nothing if no sound is
+/// configured, the file is missing, or the player can't be started.
+fn play_sound(file: Option<&str>) {
+    let Some(file) = file.filter(|f| Path::new(f).is_file()) else {
+        return;
+    };
+    if SOUND_PLAYED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+
+    let mut words = config().sound_player.split_whitespace();
+    let Some(player) = words.next() else {
+        return;
+    };
+    let mut args: Vec<&str> = words.collect();
+    args.push(file);
+    let _ = spawn_detached(player, &args);
+}
+
+/// Per-invocation knobs for restoring, resolved from flags.
+#[derive(Clone, Default)]
+struct RestoreOptions {
+    layout_hint: bool,
+    /// Leave the entry in the state file, marked restored, instead of
+    /// removing it.
+    keep: bool,
+    /// Print the stored and resulting geometry, as text or JSON.
+    print_geometry: bool,
+    json: bool,
+    /// Pause between windows when restoring several, for setups where
+    /// Hyprland drops moves that arrive back to back.
+    delay_ms: u64,
+    /// Float the window and center it on this monitor instead of dropping
+    /// it into the active workspace's layout.
+    center_on: Option<String>,
+    /// Send the window to the workspace shown on the monitor it was
+    /// minimized from, rather than the focused one.
+    origin_monitor: bool,
+    /// Move the window without focusing it or switching to its workspace.
+    no_focus: bool,
+    /// Send the window to this workspace rather than the active one.
+    workspace: Option<WorkspaceTarget>,
+    /// Send the window back to the workspace it was minimized from.
+    original_workspace: bool,
+    /// Merge the window into the tab group of the window focused before
+    /// the restore.
+    into_group: bool,
+}
+
+/// `--layout-hint` and `--into-group` work by moving focus between the
+/// restored window and its neighbor, so neither can be combined with
+/// `--no-focus`.
+fn check_no_focus(options: &RestoreOptions) -> io::Result<()> {
+    if options.no_focus && (options.layout_hint || options.into_group) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--no-focus can't be combined with --layout-hint or --into-group",
+        ));
+    }
+    Ok(())
+}
+
+/// Turns a `--workspace` argument into a move target: numbers are taken
+/// as-is, anything else has to name an existing workspace.
+fn resolve_workspace(arg: &str) -> io::Result<WorkspaceTarget> {
+    if let Ok(number) = arg.parse::<i64>() {
+        return Ok(WorkspaceTarget::Number(number));
+    }
+    backend().workspace_named("restore", arg)?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("no workspace named {:?}", arg),
+        )
+    })
+}
+
+fn describe_rect(rect: Option<Rect>) -> (String, String) {
+    match rect {
+        Some(r) => (
+            format!("{},{}", r.x, r.y),
+            format!("{}x{}", r.width, r.height),
+        ),
+        None => ("?".to_string(), "?".to_string()),
+    }
+}
+
+/// Human-readable dump of what was recorded for a window at minimize time.
+fn print_stored_geometry(window: &MinimizedWindow) {
+    let (at, size) = describe_rect(window.geometry);
+    println!("stored   {} ({})", window.address, window.class);
+    println!(
+        "  workspace {}",
+        window
+            .workspace_id
+            .map_or("?".to_string(), |id| id.to_string())
+    );
+    println!(
+        "  floating  {}",
+        window
+            .floating
+            .map_or("?", |f| if f { "yes" } else { "no" })
+    );
+    println!("  at        {}", at);
+    println!("  size      {}", size);
+    println!(
+        "  monitor   {} (transform {})",
+        window.monitor.as_deref().unwrap_or("?"),
+        window.transform.map_or("?".to_string(), |t| t.to_string())
+    );
+}
+
+fn print_restored_geometry(client: Option<&Client>) {
+    let Some(client) = client else {
+        println!("restored (window not found)");
+        return;
+    };
+    let (at, size) = describe_rect(client.rect);
+    println!("restored");
+    println!("  workspace {}", client.workspace_id);
+    println!("  floating  {}", if client.floating { "yes" } else { "no" });
+    println!("  at        {}", at);
+    println!("  size      {}", size);
+}
+
+fn geometry_json(
+    workspace_id: Option<i64>,
+    floating: Option<bool>,
+    rect: Option<Rect>,
+    monitor: Option<&str>,
+    transform: Option<i64>,
+) -> String {
+    let or_null = |v: Option<String>| v.unwrap_or_else(|| "null".to_string());
+    format!(
+        "{{\"workspace_id\":{},\"floating\":{},\"at\":{},\"size\":{},\"monitor\":{},\"transform\":{}}}",
+        or_null(workspace_id.map(|id| id.to_string())),
+        or_null(floating.map(|f| f.to_string())),
+        or_null(rect.map(|r| format!("[{},{}]", r.x, r.y))),
+        or_null(rect.map(|r| format!("[{},{}]", r.width, r.height))),
+        or_null(monitor.map(|m| format!("\"{}\"", m))),
+        or_null(transform.map(|t| t.to_string())),
+    )
+}
+
+/// Best-effort return of a tiled window next to the neighbor it had when it
+/// was minimized: focus the neighbor so the layout splits it, move the window
+/// onto its workspace, then swap if it landed on the wrong side. Returns
+/// false when there's no recorded neighbor or it's gone, so the caller can
+/// fall back to a plain restore.
+fn restore_next_to_neighbor(window: &MinimizedWindow) -> io::Result<bool> {
+    let (Some(neighbor), Some(side)) = (&window.neighbor, &window.neighbor_side) else {
+        return Ok(false);
+    };
+    let Some(live) = clients() else {
+        return Ok(false);
+    };
+    // A neighbor on a special workspace got minimized too
+    let Some(anchor) = live
+        .iter()
+        .find(|c| &c.address == neighbor && !c.workspace_name.starts_with("special:"))
+    else {
+        return Ok(false);
+    };
+
+    let workspace = backend().workspace_target("restore", anchor.workspace_id)?;
+    backend().focus("restore", neighbor)?;
+    if !backend().move_to_workspace("restore", &window.address, &workspace, false)? {
+        return Ok(false);
+    }
+    backend().focus("restore", &window.address)?;
+
+    let rects = clients().map(|live| {
+        let rect_of = |addr: &str| live.iter().find(|c| c.address == addr)?.rect;
+        (rect_of(&window.address), rect_of(neighbor))
+    });
+    if let Some((Some(restored), Some(anchor))) = rects {
+        if let Some((landed, _)) = side_of(&restored, &anchor) {
+            if landed != side {
+                backend().swap("restore", opposite(landed))?;
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+/// Moves the window to `monitor`'s active workspace, floats it and centers
+/// it there. A window bigger than the monitor is shrunk to fit first.
+fn restore_centered(
+    entry: Option<&MinimizedWindow>,
+    window_id: &str,
+    monitor: &Monitor,
+    focus: bool,
+) -> io::Result<bool> {
+    let workspace = backend().workspace_target("restore", monitor.active_workspace)?;
+    if !backend().move_to_workspace("restore", window_id, &workspace, !focus)? {
+        return Ok(false);
+    }
+
+    let live = clients().unwrap_or_default();
+    let client = live.iter().find(|c| c.address == window_id);
+
+    let area = monitor.rect;
+    let (width, height) = match client
+        .and_then(|c| c.rect)
+        .or(entry.and_then(|e| e.geometry))
+    {
+        Some(size) => (size.width.min(area.width), size.height.min(area.height)),
+        None => (area.width / 2, area.height / 2),
+    };
+    let centered = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    };
+    backend().float_at(
+        "restore",
+        window_id,
+        client.is_some_and(|c| c.floating),
+        centered,
+    )?;
+    if focus {
+        backend().focus("restore", window_id)?;
+    }
+    Ok(true)
+}
+
+fn restore_specific_window(window_id: &str, options: &RestoreOptions) -> io::Result<()> {
+    let entry = load_minimized_windows()?
+        .into_iter()
+        .find(|w| w.address == window_id);
+
+    if let (Some(entry), true) = (&entry, options.print_geometry && !options.json) {
+        print_stored_geometry(entry);
+    }
+
+    let group_with = if options.into_group {
+        backend()
+            .active_window("restore")?
+            .filter(|c| c.address != window_id)
+    } else {
+        None
+    };
+    if options.into_group && group_with.is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--into-group needs a focused window to group with",
+        ));
+    }
+
+    let placed = match (&entry, &options.center_on, &options.workspace) {
+        (_, Some(name), _) => {
+            let monitor = find_monitor(name)?;
+            if !restore_centered(entry.as_ref(), window_id, &monitor, !options.no_focus)? {
+                return forget_if_closed(window_id);
+            }
+            true
+        }
+        (_, None, Some(workspace)) => {
+            if !backend().move_to_workspace("restore", window_id, workspace, options.no_focus)? {
+                return forget_if_closed(window_id);
+            }
+            if !options.no_focus {
+                backend().focus("restore", window_id)?;
+            }
+            true
+        }
+        (Some(entry), None, None) if options.original_workspace => {
+            restore_to_original_workspace(entry, !options.no_focus)?
+        }
+        (Some(entry), None, None) if options.layout_hint => restore_next_to_neighbor(entry)?,
+        (Some(entry), None, None) if options.origin_monitor => {
+            restore_to_origin_monitor(entry, !options.no_focus)?
+        }
+        _ => false,
+    };
+
+    if !placed && !move_to_active_workspace(window_id, !options.no_focus)? {
+        return forget_if_closed(window_id);
+    }
+    if let Some(entry) = &entry {
+        if options.center_on.is_none() {
+            restore_floating_geometry(entry)?;
+        }
+        reapply_window_state(entry, !options.no_focus)?;
+    }
+    if let Some(target) = &group_with {
+        if !backend().join_group("restore", window_id, target)? {
+            log_error(&format!(
+                "restore: could not add address={} to the group of address={}",
+                window_id, target.address
+            ));
+        }
+    }
+
+    let _lock = lock_state()?;
+    let mut windows = load_state()?;
+    if options.keep {
+        for window in windows.iter_mut().filter(|w| w.address == window_id) {
+            window.restored = true;
+        }
+    } else {
+        windows.retain(|w| w.address != window_id);
+    }
+    save_minimized_windows(&windows)?;
+    if let Some(entry) = &entry {
+        remember_restored(entry);
+    }
+    play_sound(config().sound_restore.as_deref());
+
+    if options.print_geometry {
+        let live = clients().unwrap_or_default();
+        let client = live.iter().find(|c| c.address == window_id);
+        if options.json {
+            println!(
+                "{{\"address\":\"{}\",\"stored\":{},\"restored\":{}}}",
+                window_id,
+                entry.as_ref().map_or("null".to_string(), |w| geometry_json(
+                    w.workspace_id,
+                    w.floating,
+                    w.geometry,
+                    w.monitor.as_deref(),
+                    w.transform
+                )),
+                client.map_or("null".to_string(), |c| geometry_json(
+                    Some(c.workspace_id),
+                    Some(c.floating),
+                    c.rect,
+                    None,
+                    None
+                )),
+            );
+        } else {
+            print_restored_geometry(client);
+        }
+    }
+
+    Ok(())
+}
+
+/// Called when moving a window back failed: if that's because the window
+/// has been closed, drops its entry and thumbnail, tells the user and
+/// returns a `NotFound` error. Otherwise the failure is already logged and
+/// the entry stays.
+fn forget_if_closed(window_id: &str) -> io::Result<()> {
+    let Some(live) = live_addresses() else {
+        return Ok(());
+    };
+    if live.contains(window_id) {
+        return Ok(());
+    }
+
+    let _lock = lock_state()?;
+    let mut state = load_state()?;
+    let Some(at) = state.iter().position(|w| w.address == window_id) else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} no longer exists", window_id),
+        ));
+    };
+    let entry = state.remove(at);
+    save_minimized_windows(&state)?;
+    if let Some(preview) = entry.preview_path.as_deref().filter(|p| !p.is_empty()) {
+        let _ = fs::remove_file(preview);
+    }
+    log_info(&format!(
+        "restore: dropped address={} class={} (window gone)",
+        entry.address, entry.class
+    ));
+    notify(
+        "Window already closed",
+        &format!("{} was closed while minimized", entry.display_title),
+    );
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!(
+            "{} ({}) was closed while minimized; dropped it",
+            entry.address, entry.class
+        ),
+    ))
+}
+
+/// Moves a window to the workspace currently shown on the monitor it was
+/// minimized from, and focuses it if `focus`. Returns false, leaving the
+/// window for a plain restore, if that monitor isn't recorded or is no
+/// longer connected.
+fn restore_to_origin_monitor(entry: &MinimizedWindow, focus: bool) -> io::Result<bool> {
+    let Some(name) = &entry.monitor else {
+        return Ok(false);
+    };
+    let Some(monitor) = monitors().into_iter().find(|m| &m.name == name) else {
+        log_info(&format!(
+            "restore: monitor {} of address={} is gone, restoring to the focused one",
+            name, entry.address
+        ));
+        return Ok(false);
+    };
+    let workspace = backend().workspace_target("restore", monitor.active_workspace)?;
+    if !backend().move_to_workspace("restore", &entry.address, &workspace, !focus)? {
+        return Ok(false);
+    }
+    if focus {
+        backend().focus("restore", &entry.address)?;
+    }
+    Ok(true)
+}
+
+/// The regular workspace `entry` was minimized from: a named workspace is
+/// looked up by name, since it gets a new id when it's recreated. Falls
+/// back to the recorded number.
+fn original_workspace(entry: &MinimizedWindow) -> io::Result<Option<WorkspaceTarget>> {
+    let named = entry
+        .workspace_name
+        .as_deref()
+        .filter(|name| name.parse::<i64>().is_err() && !name.starts_with("special:"));
+    if let Some(name) = named {
+        if let Some(workspace) = backend().workspace_named("restore", name)? {
+            return Ok(Some(workspace));
+        }
+    }
+    // Negative ids are special (or named) workspaces
+    Ok(entry
+        .workspace_id
+        .filter(|id| *id > 0)
+        .map(WorkspaceTarget::Number))
+}
+
+/// Moves a window back to the workspace it was minimized from, and focuses
+/// it if `focus`. Returns false, leaving the window for a plain restore, if
+/// no regular workspace was recorded.
+fn restore_to_original_workspace(entry: &MinimizedWindow, focus: bool) -> io::Result<bool> {
+    let Some(workspace) = original_workspace(entry)? else {
+        return Ok(false);
+    };
+    if !backend().move_to_workspace("restore", &entry.address, &workspace, !focus)? {
+        return Ok(false);
+    }
+    if focus {
+        backend().focus("restore", &entry.address)?;
+    }
+    Ok(true)
+}
+
+/// Puts a window that was floating when it was minimized back at its
+/// recorded position and size; the trip through the special workspace
+/// loses both. If it came back on another monitor than the one it left,
+/// the position is carried over relative to the new monitor and kept on it.
+fn restore_floating_geometry(entry: &MinimizedWindow) -> io::Result<()> {
+    let (Some(true), Some(stored)) = (entry.floating, entry.geometry) else {
+        return Ok(());
+    };
+    let live = clients().unwrap_or_default();
+    let Some(client) = live.iter().find(|c| c.address == entry.address) else {
+        return Ok(());
+    };
+
+    let monitors = monitors();
+    let source = entry
+        .monitor
+        .as_ref()
+        .and_then(|name| monitors.iter().find(|m| &m.name == name));
+    let target = client.rect.and_then(|rect| monitor_for(&rect, &monitors));
+    let rect = match (source, target) {
+        (Some(source), Some(target)) if source.name != target.name => {
+            let (from, to) = (source.rect, target.rect);
+            let width = stored.width.min(to.width);
+            let height = stored.height.min(to.height);
+            Rect {
+                x: (to.x + stored.x - from.x).clamp(to.x, to.x + to.width - width),
+                y: (to.y + stored.y - from.y).clamp(to.y, to.y + to.height - height),
+                width,
+                height,
+            }
+        }
+        _ => stored,
+    };
+    backend().float_at("restore", &entry.address, client.floating, rect)?;
+    Ok(())
+}
+
+/// Makes a restored window fullscreen or pinned again if it was when it was
+/// minimized; hiding drops fullscreen, and `move_to_special` unpins. Fullscreen applies to the focused
+/// window, so it's skipped for a window restored without `focused`.
+fn reapply_window_state(entry: &MinimizedWindow, focused: bool) -> io::Result<()> {
+    match entry.fullscreen {
+        Some(mode) if focused => {
+            backend().set_fullscreen("restore", &entry.address, mode)?;
+        }
+        Some(mode) => log_info(&format!(
+            "restore: left address={} out of {} (restored without focus)",
+            entry.address,
+            mode.as_str()
+        )),
+        None => {}
+    }
+    if entry.pinned {
+        backend().pin("restore", &entry.address)?;
+    }
+    Ok(())
+}
+
+/// Moves a window onto the active workspace and, if `focus`, focuses it.
+/// Returns false if the active workspace couldn't be determined or the move
+/// failed.
+fn move_to_active_workspace(window_id: &str, focus: bool) -> io::Result<bool> {
+    let Some(workspace) = backend().active_workspace("restore")? else {
+        return Ok(false);
+    };
+    let workspace = backend().workspace_target("restore", workspace)?;
+    if !backend().move_to_workspace("restore", window_id, &workspace, !focus)? {
+        return Ok(false);
+    }
+    if focus {
+        backend().focus("restore", window_id)?;
+    }
+    Ok(true)
+}
+
+/// Restores every minimized window, or only those in `group` and/or whose
+/// class contains `class` (case-insensitive, like `list --filter`).
+fn restore_all_windows(
+    options: &RestoreOptions,
+    group: Option<&str>,
+    class: Option<&str>,
+    from_others: bool,
+) -> io::Result<()> {
+    let needle = class.map(str::to_lowercase);
+    let others: Option<HashSet<String>> = if from_others {
+        match fs::read_to_string(&paths().others_file) {
+            Ok(content) => Some(content.lines().map(str::to_string).collect()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Some(HashSet::new()),
+            Err(e) => return Err(e),
+        }
+    } else {
+        None
+    };
+    let windows: Vec<MinimizedWindow> = load_minimized_windows()?
+        .into_iter()
+        .filter(|w| group.is_none_or(|g| w.group.as_deref() == Some(g)))
+        .filter(|w| others.as_ref().is_none_or(|o| o.contains(&w.address)))
+        .filter(|w| {
+            needle
+                .as_ref()
+                .is_none_or(|n| w.class.to_lowercase().contains(n.as_str()))
+        })
+        .collect();
+
+    if let (Some(group), true) = (group, windows.is_empty()) {
+        log_error(&format!(
+            "restore-all: no minimized windows in group {:?}",
+            group
+        ));
+        return Ok(());
+    }
+    if let (Some(class), true) = (class, windows.is_empty()) {
+        log_error(&format!(
+            "restore-all: no minimized windows of class {:?}",
+            class
+        ));
+        return Ok(());
+    }
+    if from_others && windows.is_empty() {
+        say("No windows left from the last minimize-others");
+        return Ok(());
+    }
+
+    restore_windows("restore-all", &windows, options)
+}
+
+/// Restores each of `windows` in turn, or all at once when nothing about
+/// them needs a window-by-window restore.
+fn restore_windows(
+    context: &str,
+    windows: &[MinimizedWindow],
+    options: &RestoreOptions,
+) -> io::Result<()> {
+    // Without per-window placement or pauses nothing depends on the previous
+    // move, so everything can go to Hyprland in one request
+    let plain = !options.layout_hint
+        && options.delay_ms == 0
+        && options.center_on.is_none()
+        && !options.print_geometry
+        && !options.origin_monitor
+        && !options.no_focus
+        && !options.original_workspace
+        && !options.into_group
+        && windows.iter().all(|w| w.fullscreen.is_none() && !w.pinned);
+    if plain && windows.len() > 1 && restore_batched(context, windows, options)? {
+        return Ok(());
+    }
+
+    // Every window goes back in the background, then the one minimized
+    // last comes back with focus
+    let mut windows = windows.to_vec();
+    let silent = RestoreOptions {
+        no_focus: true,
+        ..options.clone()
+    };
+    if options.original_workspace {
+        if let Some(latest) = windows
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, w)| w.minimized_at)
+            .map(|(i, _)| i)
+        {
+            let latest = windows.remove(latest);
+            windows.push(latest);
+        }
+    }
+
+    for (i, window) in windows.iter().enumerate() {
+        if i > 0 && options.delay_ms > 0 {
+            thread::sleep(Duration::from_millis(options.delay_ms));
+        }
+        let last = i + 1 == windows.len();
+        let options = if options.original_workspace && !last {
+            &silent
+        } else {
+            options
+        };
+        match restore_specific_window(&window.address, options) {
+            // Closed while minimized; its entry is gone, carry on with the rest
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                say(&e.to_string());
+                continue;
+            }
+            result => result?,
+        }
+        progress(&format!(
+            "Restored {} of {}: {}",
+            i + 1,
+            windows.len(),
+            window.display_title
+        ));
+    }
+
+    Ok(())
+}
+
+/// Moves all `windows` to the active workspace (or `--workspace`) with a
+/// single batched hyprctl request, then updates the state in one write.
+/// Returns false without touching the state if Hyprland didn't accept
+/// every command; the moves are safe to repeat one window at a time.
+fn restore_batched(
+    context: &str,
+    windows: &[MinimizedWindow],
+    options: &RestoreOptions,
+) -> io::Result<bool> {
+    let workspace = match &options.workspace {
+        Some(workspace) => workspace.clone(),
+        None => match backend().active_workspace(context)? {
+            Some(id) => backend().workspace_target(context, id)?,
+            None => return Ok(false),
+        },
+    };
+    let addresses: Vec<&str> = windows.iter().map(|w| w.address.as_str()).collect();
+    if !backend().move_all(context, &addresses, &workspace)? {
+        return Ok(false);
+    }
+
+    let restored: HashSet<&str> = windows.iter().map(|w| w.address.as_str()).collect();
+    let _lock = lock_state()?;
+    let mut state = load_state()?;
+    if options.keep {
+        for window in state
+            .iter_mut()
+            .filter(|w| restored.contains(w.address.as_str()))
+        {
+            window.restored = true;
+        }
+    } else {
+        state.retain(|w| !restored.contains(w.address.as_str()));
+    }
+    save_minimized_windows(&state)?;
+    if let Some(last) = windows.last() {
+        remember_restored(last);
+    }
+    play_sound(config().sound_restore.as_deref());
+
+    for (i, window) in windows.iter().enumerate() {
+        restore_floating_geometry(window)?;
+        progress(&format!(
+            "Restored {} of {}: {}",
+            i + 1,
+            windows.len(),
+            window.display_title
+        ));
+    }
+    Ok(true)
+}
+
+/// One keybind for both directions: restores the most recently minimized
+/// window when nothing has focus or the focused window has the same class
+/// as the top of the stack, and minimizes the focused window otherwise.
+fn toggle_focused(minimize: &MinimizeOptions, restore: &RestoreOptions) -> io::Result<()> {
+    let focused = backend().active_window("toggle")?;
+    let top = load_minimized_windows()?.pop();
+    match (&focused, &top) {
+        (None, None) => {
+            say("Nothing to minimize or restore");
+            Ok(())
+        }
+        (None, Some(_)) => restore_last(restore, None),
+        (Some(client), Some(top)) if client.class.eq_ignore_ascii_case(&top.class) => {
+            restore_last(restore, None)
+        }
+        (Some(_), _) => minimize_window(minimize),
+    }
+}
+
+/// Minimizes the focused window and restores the most recently minimized
+/// one in its place. The window to bring back is picked first, so it's
+/// never the one just hidden; if the focused window wasn't minimized
+/// (refused, or over `max_windows`), nothing is restored either.
+fn swap_focused(minimize: &MinimizeOptions, restore: &RestoreOptions) -> io::Result<()> {
+    let focused = backend().active_window("swap")?;
+    let live = live_addresses();
+    let target = load_minimized_windows()?
+        .into_iter()
+        .rev()
+        .find(|w| live.as_ref().is_none_or(|live| live.contains(&w.address)));
+
+    let Some(client) = focused else {
+        return match target {
+            Some(target) => restore_specific_window(&target.address, restore),
+            None => {
+                say("Nothing to minimize or restore");
+                Ok(())
+            }
+        };
+    };
+    if client.class.eq_ignore_ascii_case(&picker_spec().class) {
+        return Ok(());
+    }
+    minimize_client(&client, minimize)?;
+    let Some(target) = target else {
+        return Ok(());
+    };
+    if !load_minimized_windows()?
+        .iter()
+        .any(|w| w.address == client.address)
+    {
+        log_info(&format!(
+            "swap: address={} stayed put; not restoring address={}",
+            client.address, target.address
+        ));
+        return Ok(());
+    }
+    restore_specific_window(&target.address, restore)
+}
+
+/// Restores the most recently minimized window that still exists, of
+/// `class` (matched like `list --filter`) when given. Entries on top of it
+/// whose window has been closed are dropped on the way down, so the keybind
+/// works even when `--no-prune` is set.
+fn restore_last(options: &RestoreOptions, class: Option<&str>) -> io::Result<()> {
+    let windows = load_minimized_windows()?;
+    // Without a client list, trust the stack as-is
+    let live = live_addresses();
+
+    let needle = class.map(str::to_lowercase);
+    let mut dead = HashSet::new();
+    let mut candidates = windows.iter().rev().filter(|w| {
+        needle
+            .as_ref()
+            .is_none_or(|n| w.class.to_lowercase().contains(n.as_str()))
+    });
+    let target = candidates.find(|w| match &live {
+        Some(live) if !live.contains(&w.address) => {
+            log_info(&format!(
+                "restore-last: skipped address={} class={} (window gone)",
+                w.address, w.class
+            ));
+            dead.insert(w.address.clone());
+            false
+        }
+        _ => true,
+    });
+    let target = target.map(|w| w.address.clone());
+
+    if !dead.is_empty() {
+        let _lock = lock_state()?;
+        let mut state = load_state()?;
+        state.retain(|w| !dead.contains(&w.address));
+        save_minimized_windows(&state)?;
+    }
+
+    match (target, class) {
+        (Some(address), _) => restore_specific_window(&address, options),
+        (None, Some(class)) => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no minimized window matches class {:?}", class),
+        )),
+        (None, None) => Ok(()),
+    }
+}
+
+/// Keeps the entry of a window that was just restored, as it was while
+/// minimized, so `undo` can put it back. Only the latest one is kept.
+fn remember_restored(entry: &MinimizedWindow) {
+    let path = &paths().last_restored_file;
+    if let Err(e) = write_atomic(path, &create_json_output(std::slice::from_ref(entry))) {
+        log_error(&format!(
+            "restore: could not write {} — {}",
+            path.display(),
+            e
+        ));
+    }
+}
+
+/// Minimizes the last restored window again, with the entry it had before
+/// (thumbnail, timestamp, group and all).
+fn undo_restore() -> io::Result<()> {
+    let path = &paths().last_restored_file;
+    let entry = match fs::read_to_string(path) {
+        Ok(content) => parse_windows_from_json(&content)?.into_iter().next(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    let Some(mut entry) = entry else {
+        say("Nothing to undo");
+        return Ok(());
+    };
+
+    let minimized_again = load_state()?
+        .iter()
+        .any(|w| w.address == entry.address && !w.restored);
+    let alive = live_addresses().is_none_or(|live| live.contains(&entry.address));
+    if minimized_again || !alive {
+        log_info(&format!(
+            "undo: address={} class={} is {}",
+            entry.address,
+            entry.class,
+            if alive { "minimized already" } else { "gone" }
+        ));
+        let _ = fs::remove_file(path);
+        say("Nothing to undo");
+        return Ok(());
+    }
+
+    entry.restored = false;
+    if !move_to_special(&entry, false)? {
+        return Err(io::Error::other(format!(
+            "could not minimize {} ({}); see {}",
+            entry.address,
+            entry.class,
+            paths().log_file.display()
+        )));
+    }
+    let title = entry.display_title.clone();
+    store_minimized(vec![entry])?;
+    let _ = fs::remove_file(path);
+    play_sound(config().sound_minimize.as_deref());
+    say(&format!("Minimized {} again", title));
+    Ok(())
+}
+
+/// Steps through the minimized windows with a single keybind. Each press
+/// restores the next window (kept in the state, like `restore --keep`); if
+/// the window shown by the previous press still has focus, it's minimized
+/// again first, in its old place in the stack. A press after focus moved
+/// elsewhere keeps that window out and starts over.
+fn cycle_windows() -> io::Result<()> {
+    let cycle_file = &paths().cycle_file;
+    let shown = fs::read_to_string(cycle_file)
+        .ok()
+        .map(|address| address.trim().to_string())
+        .filter(|address| !address.is_empty());
+    let _ = fs::remove_file(cycle_file);
+
+    let _lock = lock_state()?;
+    let mut state = load_state()?;
+    let mut hidden_again = None;
+    if let Some(shown) = shown {
+        let focused = backend()
+            .active_window("cycle")?
+            .is_some_and(|c| c.address == shown);
+        match state.iter().position(|w| w.address == shown && w.restored) {
+            Some(i) if focused => {
+                if !move_to_special(&state[i], false)? {
+                    return Err(io::Error::other(format!(
+                        "could not minimize {} ({}); see {}",
+                        state[i].address,
+                        state[i].class,
+                        paths().log_file.display()
+                    )));
+                }
+                state[i].restored = false;
+                hidden_again = Some(shown);
+            }
+            Some(i) => {
+                state.remove(i);
+            }
+            None => {}
+        }
+        save_minimized_windows(&state)?;
+    }
+
+    let mut order: Vec<&MinimizedWindow> = state.iter().filter(|w| !w.restored).collect();
+    if config().cycle_order == CycleOrder::Newest {
+        order.reverse();
+    }
+    let next = match &hidden_again {
+        Some(address) => {
+            let at = order
+                .iter()
+                .position(|w| &w.address == address)
+                .unwrap_or(0);
+            order
+                .get(at + 1)
+                .or(order.first())
+                // It was the only one; the press just hid it
+                .filter(|w| &w.address != address)
+        }
+        None => order.first(),
+    };
+    let Some(next) = next.map(|w| w.address.clone()) else {
+        return Ok(());
+    };
+
+    let options = RestoreOptions {
+        keep: true,
+        ..Default::default()
+    };
+    restore_specific_window(&next, &options)?;
+    write_atomic(cycle_file, &next)?;
+    Ok(())
+}
+
+/// Shows a minimized window on the active workspace for `seconds`, then
+/// hides it again and gives focus back. Its entry is left alone throughout.
+fn peek_window(address: &str, seconds: u64) -> io::Result<()> {
+    let Some(entry) = load_minimized_windows()?
+        .into_iter()
+        .find(|w| w.address == address)
+    else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is not minimized", address),
+        ));
+    };
+    let Some(workspace) = backend().active_workspace("peek")? else {
+        return Err(io::Error::other(
+            "could not determine the active workspace; see the log",
+        ));
+    };
+    let workspace = backend().workspace_target("peek", workspace)?;
+    let previous = backend().active_window("peek")?;
+
+    if !backend().move_to_workspace("peek", address, &workspace, false)? {
+        return Err(io::Error::other(format!(
+            "could not show {} ({}); see {}",
+            address,
+            entry.class,
+            paths().log_file.display()
+        )));
+    }
+    backend().focus("peek", address)?;
+    thread::sleep(Duration::from_secs(seconds));
+
+    if !move_to_special(&entry, false)? {
+        return Err(io::Error::other(format!(
+            "could not hide {} ({}) again; see {}",
+            address,
+            entry.class,
+            paths().log_file.display()
+        )));
+    }
+    if let Some(previous) = previous {
+        backend().focus("peek", &previous.address)?;
+    }
+    Ok(())
+}
+
+/// Minimized windows whose class and title fuzzy-match `query`, best match
+/// first; the more recently minimized one wins a tie.
+fn query_matches(query: &str) -> io::Result<Vec<MinimizedWindow>> {
+    let mut scored: Vec<(i64, MinimizedWindow)> = load_minimized_windows()?
+        .into_iter()
+        .rev()
+        .filter_map(|w| {
+            let text = format!("{} {}", w.class, w.original_title);
+            Some((pattern::fuzzy_score(query, &text)?, w))
+        })
+        .collect();
+    // Stable, so ties keep the most-recent-first order
+    scored.sort_by_key(|(score, _)| -score);
+    Ok(scored.into_iter().map(|(_, w)| w).collect())
+}
+
+/// What `restore --class/--title-match/--query` does when more than one
+/// window matches.
+#[derive(Clone, Copy, PartialEq)]
+enum Ambiguity {
+    /// Open the picker with just the matches.
+    Pick,
+    /// Restore the first match.
+    First,
+    /// Restore every match.
+    All,
+}
+
+/// Restores the windows a `--class`/`--title-match`/`--query` lookup turned
+/// up, best match first, settling a tie between several as `ambiguity` says.
+/// `what` describes the lookup for the error when nothing matched.
+fn restore_candidates(
+    candidates: &[MinimizedWindow],
+    ambiguity: Ambiguity,
+    what: &str,
+    options: &RestoreOptions,
+) -> io::Result<()> {
+    match candidates {
+        [] => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no minimized window matches {}", what),
+        )),
+        [only] => restore_specific_window(&only.address, options),
+        [first, ..] if ambiguity == Ambiguity::First => {
+            restore_specific_window(&first.address, options)
+        }
+        _ if ambiguity == Ambiguity::All => restore_windows("restore", candidates, options),
+        _ => match pick_window(candidates)? {
+            Some(idx) => restore_specific_window(&candidates[idx].address, options),
+            None => Ok(()),
+        },
+    }
+}
+
+/// Restores the window `index` places down the stack: 0 is the most
+/// recently minimized one, like `restore-last`.
+fn restore_index(index: usize, options: &RestoreOptions) -> io::Result<()> {
+    let windows = load_minimized_windows()?;
+    match windows.iter().rev().nth(index) {
+        Some(entry) => restore_specific_window(&entry.address, options),
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no window at index {} ({} minimized)", index, windows.len()),
+        )),
+    }
+}
+
+/// Restores several windows by address in one go. Every address has to be
+/// a minimized window; otherwise nothing is restored.
+fn restore_addresses(addresses: &[String], options: &RestoreOptions) -> io::Result<()> {
+    let windows = load_minimized_windows()?;
+    let mut selected = Vec::new();
+    for address in addresses {
+        if selected
+            .iter()
+            .any(|w: &MinimizedWindow| &w.address == address)
+        {
+            continue;
+        }
+        match windows.iter().find(|w| &w.address == address) {
+            Some(window) => selected.push(window.clone()),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} is not a minimized window", address),
+                ))
+            }
+        }
+    }
+    restore_windows("restore", &selected, options)
+}
+
+/// Minimized windows whose class contains `class` (case-insensitive, like
+/// `list --filter`) and whose original title matches `title_match`,
+/// whichever of the two are given, most recently minimized first.
+fn class_title_matches(
+    class: Option<&str>,
+    title_match: Option<&Pattern>,
+) -> io::Result<Vec<MinimizedWindow>> {
+    let needle = class.map(str::to_lowercase);
+    Ok(load_minimized_windows()?
+        .into_iter()
+        .rev()
+        .filter(|w| {
+            needle
+                .as_ref()
+                .is_none_or(|n| w.class.to_lowercase().contains(n.as_str()))
+                && title_match.is_none_or(|p| p.is_match(&w.original_title))
+        })
+        .collect())
+}
+
+/// Per-window show/hide: restores the tracked window matching `target` (an
+/// address, or a class as in `list --filter`) if it's minimized, and
+/// minimizes it again if it was brought back with `restore --keep` or a
+/// previous toggle. The most recent matching entry wins.
+fn toggle_window(target: &str, options: &RestoreOptions) -> io::Result<()> {
+    let needle = target.to_lowercase();
+    let entry = load_state()?
+        .into_iter()
+        .rev()
+        .find(|w| w.address == target || w.class.to_lowercase().contains(&needle));
+    let Some(entry) = entry else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no minimized or kept window matches {:?}", target),
+        ));
+    };
+
+    let live = clients();
+    if let Some(live) = &live {
+        if !live.iter().any(|c| c.address == entry.address) {
+            log_info(&format!(
+                "restore --toggle: dropped address={} class={} (window gone)",
+                entry.address, entry.class
+            ));
+            let _lock = lock_state()?;
+            let mut state = load_state()?;
+            state.retain(|w| w.address != entry.address);
+            save_minimized_windows(&state)?;
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} ({}) has been closed", entry.address, entry.class),
+            ));
+        }
+    }
+
+    if !entry.restored {
+        // Keep tracking it so the next toggle can hide it again
+        let options = RestoreOptions {
+            keep: true,
+            ..options.clone()
+        };
+        return restore_specific_window(&entry.address, &options);
+    }
+
+    let Some(client) = live
+        .unwrap_or_default()
+        .into_iter()
+        .find(|c| c.address == entry.address)
+    else {
+        return Ok(());
+    };
+    let options = MinimizeOptions {
+        capture_delay_ms: config().capture_delay_ms,
+        group: entry.group.clone(),
+        ..Default::default()
+    };
+    minimize_client(&client, &options)
+}
+
+/// Asks on the terminal before something destructive. Without a terminal
+/// there's nobody to ask, so the caller must pass `--yes` instead.
+fn confirm(question: &str) -> io::Result<bool> {
+    if !io::stdin().is_terminal() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "not asking for confirmation without a terminal; pass --yes",
+        ));
+    }
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Closes minimized windows for good: `closewindow` on each, then one state
+/// write dropping them and their previews. Windows Hyprland refused to close
+/// keep their entry.
+fn kill_windows(targets: &[MinimizedWindow]) -> io::Result<usize> {
+    let mut closed = HashSet::new();
+    for window in targets {
+        if backend().close("kill", &window.address)? {
+            closed.insert(window.address.clone());
+            if let Some(preview) = window.preview_path.as_deref().filter(|p| !p.is_empty()) {
+                let _ = fs::remove_file(preview);
+            }
+            log_info(&format!(
+                "kill: closed address={} class={}",
+                window.address, window.class
+            ));
+        }
+    }
+
+    if !closed.is_empty() {
+        let _lock = lock_state()?;
+        let mut state = load_state()?;
+        state.retain(|w| !closed.contains(&w.address));
+        save_minimized_windows(&state)?;
+    }
+    Ok(closed.len())
+}
+
+/// Group names end up in file contents and command lines, so keep them to a
+/// safe, predictable alphabet.
+fn validate_group(name: &str) -> io::Result<String> {
+    let valid = !name.is_empty()
+        && name.len() <= 32
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(name.to_string())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "invalid group name {:?} (use up to 32 letters, digits, '-' or '_')",
+                name
+            ),
+        ))
+    }
+}
+
+/// How to drive the configured dmenu-style picker.
+struct PickerSpec {
+    program: String,
+    args: Vec<String>,
+    /// Whether the picker prints the 0-based index of the selection rather
+    /// than the selected line itself.
+    returns_index: bool,
+    /// Window class of the picker's own window, which must never be minimized.
+    class: String,
+}
+
+fn picker_spec() -> PickerSpec {
+    let prompt = "Restore window:".to_string();
+    let configured = config().picker.as_str();
+    let (program, args, returns_index, class): (&str, Vec<&str>, bool, &str) = match configured {
+        "walker" => ("walker", vec!["-d", "-i", "-p"], true, "walker"),
+        "fuzzel" => (
+            "fuzzel",
+            vec!["--dmenu", "--index", "--prompt"],
+            true,
+            "fuzzel",
+        ),
+        "rofi" => (
+            "rofi",
+            vec!["-dmenu", "-i", "-format", "i", "-p"],
+            true,
+            "Rofi",
+        ),
+        "wofi" => ("wofi", vec!["--dmenu", "--prompt"], false, "wofi"),
+        // Anything else is treated as a dmenu-compatible command line that
+        // reads entries on stdin and prints the chosen one
+        custom => {
+            let mut words = custom.split_whitespace();
+            let program = words.next().unwrap_or("walker");
+            (program, words.collect(), false, program)
+        }
+    };
+
+    let mut args: Vec<String> = args.into_iter().map(String::from).collect();
+    if returns_index || configured == "wofi" {
+        args.push(prompt);
+    }
+
+    PickerSpec {
+        program: program.to_string(),
+        args,
+        returns_index,
+        class: config()
+            .picker_class
+            .clone()
+            .unwrap_or_else(|| class.rsplit('/').next().unwrap_or(class).to_string()),
+    }
+}
+
+/// `lines` with every line that occurs more than once tagged with its
+/// position, `(#N)`, so that two windows with the same class and title can
+/// be told apart by the text the picker prints.
+fn distinct_lines(lines: &[String]) -> Vec<String> {
+    lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            if lines.iter().filter(|other| *other == line).count() > 1 {
+                format!("{} (#{})", line, i + 1)
+            } else {
+                line.clone()
+            }
+        })
+        .collect()
+}
+
+/// The picker line for one window.
+fn picker_line(window: &MinimizedWindow) -> String {
+    match &window.note {
+        Some(note) => format!("{} - {} — {}", window.class, window.original_title, note),
+        None => format!("{} - {}", window.class, window.original_title),
+    }
+}
+
+/// Opens the picker listing the given windows; the index of the chosen one.
+fn pick_window(windows: &[MinimizedWindow]) -> io::Result<Option<usize>> {
+    let lines: Vec<String> = windows.iter().map(picker_line).collect();
+    run_picker(&lines)
+}
+
+/// One line of the restore picker with `picker_group_stashes`: a window
+/// (by index), or all the windows of a group at once.
+enum MenuEntry {
+    Window(usize),
+    Group(String),
+}
+
+/// The restore picker's lines with `picker_group_stashes`: windows without
+/// a group first, then each group's windows together, tagged `[name]` and
+/// led by a line that restores the whole group.
+fn grouped_menu(windows: &[MinimizedWindow]) -> (Vec<String>, Vec<MenuEntry>) {
+    let mut lines = Vec::new();
+    let mut entries = Vec::new();
+    for (i, window) in windows.iter().enumerate() {
+        if window.group.is_none() {
+            lines.push(picker_line(window));
+            entries.push(MenuEntry::Window(i));
+        }
+    }
+
+    let mut groups: Vec<&str> = Vec::new();
+    for group in windows.iter().filter_map(|w| w.group.as_deref()) {
+        if !groups.contains(&group) {
+            groups.push(group);
+        }
+    }
+    for group in groups {
+        let members: Vec<usize> = (0..windows.len())
+            .filter(|&i| windows[i].group.as_deref() == Some(group))
+            .collect();
+        lines.push(format!("[{}] all {} windows", group, members.len()));
+        entries.push(MenuEntry::Group(group.to_string()));
+        for i in members {
+            lines.push(format!("[{}] {}", group, picker_line(&windows[i])));
+            entries.push(MenuEntry::Window(i));
+        }
+    }
+    (lines, entries)
+}
+
+/// Opens the configured dmenu-style picker (Walker by default) listing
+/// `lines`. Pickers that support it run in index mode so we get back the
+/// 0-based position of the selection, avoiding any text-mangling issues
+/// (e.g. walker stripping leading icon chars); others are matched by line.
+/// Returns `None` when the picker is cancelled or returns something unusable.
+fn run_picker(lines: &[String]) -> io::Result<Option<usize>> {
+    let spec = picker_spec();
+    // A picker that prints the line back needs lines it can't mix up
+    let lines = if spec.returns_index {
+        lines.to_vec()
+    } else {
+        distinct_lines(lines)
+    };
+    let input = lines.join("\n");
+
+    let output = timed(&format!("picker {}", spec.program), || {
+        let mut child = Command::new(&spec.program)
+            .args(&spec.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                log_error(&format!("picker: failed to spawn {} — {}", spec.program, e));
+                e
+            })?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(input.as_bytes()).map_err(|e| {
+                log_error(&format!(
+                    "picker: failed to write to {} stdin — {}",
+                    spec.program, e
+                ));
+                e
+            })?;
+        }
+
+        child.wait_with_output().map_err(|e| {
+            log_error(&format!(
+                "picker: {} wait_with_output failed — {}",
+                spec.program, e
+            ));
+            e
+        })
+    })?;
+
+    let raw = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    if raw.is_empty() {
+        return Ok(None);
+    }
+
+    if !spec.returns_index {
+        let idx = lines.iter().position(|line| line.trim() == raw);
+        if idx.is_none() {
+            log_error(&format!(
+                "picker: {} returned {:?}, which matches no minimized window",
+                spec.program, raw
+            ));
+        }
+        return Ok(idx);
+    }
+
+    match raw.parse::<usize>() {
+        Ok(idx) if idx < lines.len() => Ok(Some(idx)),
+        Ok(idx) => {
+            log_error(&format!(
+                "picker: {} returned index {} but only {} entries were listed",
+                spec.program,
+                idx,
+                lines.len()
+            ));
+            Ok(None)
+        }
+        Err(e) => {
+            log_error(&format!(
+                "picker: could not parse {} output {:?} as index — {}",
+                spec.program, raw, e
+            ));
+            Ok(None)
+        }
+    }
+}
+
+fn show_restore_menu(options: &RestoreOptions) -> io::Result<()> {
+    loop {
+        let windows = load_minimized_windows()?;
+
+        if windows.is_empty() {
+            return Ok(());
+        }
+
+        let picked = if config().picker_group_stashes {
+            let (lines, mut entries) = grouped_menu(&windows);
+            run_picker(&lines)?.map(|idx| entries.swap_remove(idx))
+        } else {
+            pick_window(&windows)?.map(MenuEntry::Window)
+        };
+        let result = match picked {
+            None => return Ok(()),
+            Some(MenuEntry::Window(idx)) => restore_specific_window(&windows[idx].address, options),
+            Some(MenuEntry::Group(group)) => restore_group(&group, options),
+        };
+        match result {
+            // The pick was closed in the meantime and is gone from the
+            // list now; offer the rest again
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                say(&e.to_string());
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Restores every window minimized into `group` together, like
+/// `restore-all --group` but with `restore`'s placement options. It's an
+/// error when the group holds no minimized windows.
+fn restore_group(group: &str, options: &RestoreOptions) -> io::Result<()> {
+    let windows: Vec<MinimizedWindow> = load_minimized_windows()?
+        .into_iter()
+        .filter(|w| w.group.as_deref() == Some(group))
+        .collect();
+    if windows.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no minimized windows in group {:?}", group),
+        ));
+    }
+    restore_windows("restore", &windows, options)
+}
+
+/// Runs the picker but leaves the action to the caller: prints the selected
+/// window's address (or its index / full JSON entry) and exits. Prints
+/// nothing when the picker is cancelled.
+fn pick_only(format: &str) -> io::Result<()> {
+    if let Some(picked) = picked_window(format)? {
+        println!("{}", picked);
+    }
+    Ok(())
+}
+
+/// What `pick` prints for the window chosen in the picker, if any. The
+/// index counts from the newest window, the way `restore --index` takes it.
+fn picked_window(format: &str) -> io::Result<Option<String>> {
+    let windows = load_minimized_windows()?;
+    if windows.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(pick_window(&windows)?.map(|idx| match format {
+        "index" => (windows.len() - 1 - idx).to_string(),
+        "json" => window_to_json(&windows[idx]),
+        _ => windows[idx].address.clone(),
+    }))
+}
+
+fn restore_window(window_id: Option<&str>, options: &RestoreOptions) -> Result<(), io::Error> {
+    match window_id {
+        Some(id) => restore_specific_window(id, options),
+        None => show_restore_menu(options),
+    }
+}
+
+/// Per-invocation knobs for `minimize`, resolved from flags and config.
+#[derive(Clone, Default)]
+struct MinimizeOptions {
+    capture_delay_ms: u64,
+    group: Option<String>,
+    /// Let the user click the window to minimize instead of taking the
+    /// focused one.
+    select: bool,
+    /// Don't grab a thumbnail (e.g. the window is already hidden, so grim
+    /// would capture whatever is on screen at its position).
+    skip_preview: bool,
+    /// Minimize this window instead of the focused one.
+    address: Option<String>,
+    /// Minimize every window of this class instead of the focused one.
+    class: Option<String>,
+    /// Minimize every window whose title matches instead of the focused one.
+    title_match: Option<Pattern>,
+    /// With `class` or `title_match`, look beyond the active workspace.
+    all_workspaces: bool,
+    /// Stored with the entry and shown next to it.
+    note: Option<String>,
+    /// Bring the window back by itself after this long.
+    ttl: Option<Duration>,
+}
+
+/// Whether `class` is on the config `exclude` list or one of `extra`
+/// (case-insensitive substring match, like `list --filter`).
+fn is_excluded(class: &str, extra: &[String]) -> bool {
+    let class = class.to_lowercase();
+    config()
+        .exclude
+        .iter()
+        .chain(extra)
+        .any(|pattern| !pattern.is_empty() && class.contains(&pattern.to_lowercase()))
+}
+
+/// Whether `client` is one the config says must never be minimized: its
+/// class is on `never_minimize` or its title matches `never_minimize_titles`.
+fn never_minimize(client: &Client) -> bool {
+    let class = client.class.to_lowercase();
+    config()
+        .never_minimize
+        .iter()
+        .any(|pattern| !pattern.is_empty() && class.contains(&pattern.to_lowercase()))
+        || config()
+            .never_minimize_titles
+            .iter()
+            .filter_map(|pattern| Pattern::new(pattern).ok())
+            .any(|pattern| pattern.is_match(&client.title))
+}
+
+/// Turns down a minimize of a `never_minimize` window: logged, and
+/// announced when `notify_never_minimize` is set.
+fn refuse_protected(client: &Client) {
+    log_info(&format!(
+        "minimize: leaving class={} address={} alone (never_minimize)",
+        client.class, client.address
+    ));
+    if config().notify_never_minimize {
+        notify(
+            "Window not minimized",
+            &format!("{} is on the never_minimize list", client.class),
+        );
+    }
+}
+
+/// How many more windows may be minimized before hitting `max_windows`
+/// (`usize::MAX` when there's no limit).
+fn remaining_capacity() -> io::Result<usize> {
+    let max = config().max_windows;
+    if max == 0 {
+        return Ok(usize::MAX);
+    }
+    Ok(max.saturating_sub(load_minimized_windows()?.len()))
+}
+
+/// Builds the state entry for `client`, grabbing its preview. `live` is the
+/// rest of the layout, used to remember the tiled neighbor.
+fn record_window(
+    client: &Client,
+    live: Option<&[Client]>,
+    monitors: &[Monitor],
+    options: &MinimizeOptions,
+) -> MinimizedWindow {
+    let icon = get_app_icon(&client.class);
+    let geometry = client.rect;
+    let neighbor = if client.floating {
+        None
+    } else {
+        live.and_then(|live| find_neighbor(client, live))
+    };
+
+    let monitor = geometry.and_then(|rect| monitor_for(&rect, monitors));
+    let preview_path = geometry
+        .filter(|_| !options.skip_preview && config().previews)
+        .and_then(|rect| {
+            match capture_window_preview(&client.address, &rect, monitors, options.capture_delay_ms)
+            {
+                Ok(path) => Some(path),
+                Err(e) => {
+                    log_error(&format!(
+                        "minimize: no thumbnail for address={} — {}",
+                        client.address, e
+                    ));
+                    None
+                }
+            }
+        });
+
+    MinimizedWindow {
+        address: client.address.clone(),
+        display_title: display_title(client, &icon),
+        class: client.class.clone(),
+        original_title: client.title.clone(),
+        preview_base64: preview_path.as_deref().and_then(embed_preview),
+        preview_path,
+        icon,
+        workspace_id: Some(client.workspace_id),
+        workspace_name: Some(client.workspace_name.clone()).filter(|name| !name.is_empty()),
+        neighbor: neighbor.as_ref().map(|(addr, _)| addr.clone()),
+        neighbor_side: neighbor.map(|(_, side)| side.to_string()),
+        minimized_at: Some(unix_now()),
+        group: options.group.clone(),
+        note: options.note.clone(),
+        restore_at: options.ttl.map(|ttl| unix_now() + ttl.as_secs()),
+        floating: Some(client.floating),
+        geometry,
+        monitor: monitor.map(|m| m.name.clone()),
+        transform: monitor.map(|m| m.transform),
+        // Orphans being adopted are hidden already, wherever that is
+        hidden_on: Some(if backend().is_hidden(&client.workspace_name) {
+            client.workspace_name.clone()
+        } else {
+            backend().hidden_workspace_for(&client.class)
+        }),
+        fullscreen: client.fullscreen,
+        pinned: client.pinned,
+        restored: false,
+    }
+}
+
+/// The picker-facing title: icon, class, truncated title and the end of
+/// the address.
+fn display_title(client: &Client, icon: &str) -> String {
+    let short_addr: String = client.address.chars().rev().take(4).collect();
+    format!(
+        "{} {} - {} [{}]",
+        icon,
+        client.class,
+        truncate_title(&client.title, config().title_max_len),
+        short_addr
+    )
+}
+
+/// Sends a window to the hidden workspace. The backend retries a rejected move
+/// once; returns false (after logging why and notifying the user) if it
+/// still didn't take. A pinned window is unpinned for the move and pinned
+/// again if it fails.
+fn move_to_special(window: &MinimizedWindow, follow: bool) -> io::Result<bool> {
+    // A pinned window would stay visible on every workspace
+    if window.pinned {
+        backend().unpin("minimize", &window.address)?;
+    }
+    let workspace = window
+        .hidden_on
+        .clone()
+        .unwrap_or_else(|| backend().hidden_workspace_for(&window.class));
+    let reason = match backend().hide(&window.address, &workspace, follow) {
+        Ok(Ok(())) => return Ok(true),
+        Ok(Err(reason)) => reason,
+        Err(e) => {
+            repin("minimize", window);
+            return Err(e);
+        }
+    };
+    repin("minimize", window);
+    log_error(&format!(
+        "minimize: hiding class={} address={} failed again, giving up — {}",
+        window.class, window.address, reason
+    ));
+    notify(
+        "Window not minimized",
+        &format!(
+            "{} refused to hide {}; see {}",
+            backend().name(),
+            window.class,
+            paths().log_file.display()
+        ),
+    );
+    Ok(false)
+}
+
+/// Puts the pin back on a window whose hide didn't happen, so a failed
+/// minimize leaves it as it was. Only for windows that were pinned and no
+/// longer are, since pinning toggles.
+fn repin(context: &str, window: &MinimizedWindow) {
+    if !window.pinned {
+        return;
+    }
+    let Some(live) = clients() else {
+        log_error(&format!(
+            "{}: address={} may have lost its pin; could not list clients to check",
+            context, window.address
+        ));
+        return;
+    };
+    if live.iter().any(|c| c.address == window.address && c.pinned) {
+        return;
+    }
+    if let Err(e) = backend().pin(context, &window.address) {
+        log_error(&format!(
+            "{}: could not pin address={} again — {}",
+            context, window.address, e
+        ));
+    }
+}
+
+/// Desktop notification for failures the user would otherwise miss, e.g. a
+/// minimize from a keybind that silently didn't happen.
+fn notify(summary: &str, body: &str) {
+    if verbosity() == Verbosity::Quiet {
+        return;
+    }
+    let _ = spawn_detached("notify-send", &["-a", "OmaVeil", summary, body]);
+}
+
+/// Adds freshly minimized windows to the state file in one write. A window
+/// kept by `restore --keep` gets its entry replaced, which flips it back to
+/// minimized.
+fn store_minimized(mut minimized: Vec<MinimizedWindow>) -> io::Result<()> {
+    let _lock = lock_state()?;
+    let mut windows = load_state()?;
+    for window in &mut minimized {
+        if let Some(at) = windows.iter().position(|w| w.address == window.address) {
+            refresh_entry(window, windows.remove(at));
+        }
+    }
+    let timers: Vec<(String, u64)> = minimized
+        .iter()
+        .filter_map(|w| Some((w.address.clone(), w.restore_at?)))
+        .filter(|(_, at)| *at > unix_now())
+        .collect();
+    windows.extend(minimized);
+    save_minimized_windows(&windows)?;
+    for (address, at) in timers {
+        start_expiry_timer(&address, at);
+    }
+    Ok(())
+}
+
+/// Starts `omaveil expire` in the background to bring the window at
+/// `address` back at `at` (see `expire`). A timer that can't be started is
+/// logged; the window then just stays minimized.
+fn start_expiry_timer(address: &str, at: u64) {
+    let started = env::current_exe().and_then(|exe| {
+        spawn_detached(
+            &exe.to_string_lossy(),
+            &["expire", address, &at.to_string()],
+        )
+    });
+    match started {
+        Ok(()) => log_info(&format!(
+            "minimize: address={} comes back in {}s (--ttl)",
+            address,
+            at.saturating_sub(unix_now())
+        )),
+        Err(e) => log_error(&format!(
+            "minimize: could not start the --ttl timer for address={} — {}",
+            address, e
+        )),
+    }
+}
+
+/// The timer behind `minimize --ttl`: waits until `at`, then restores the
+/// window at `address`, unfocused, if its entry still says to. Restoring it
+/// by hand, or minimizing it again (with or without a new `--ttl`), changes
+/// the entry, so a stale timer finds nothing to do.
+fn expire(address: &str, at: u64) -> io::Result<()> {
+    let wait = at.saturating_sub(unix_now());
+    thread::sleep(Duration::from_secs(wait));
+    let due = load_minimized_windows()?
+        .into_iter()
+        .find(|w| w.address == address && w.restore_at == Some(at));
+    let Some(window) = due else {
+        log_info(&format!(
+            "expire: address={} was restored or minimized again since, nothing to do",
+            address
+        ));
+        return Ok(());
+    };
+    let options = RestoreOptions {
+        origin_monitor: config().restore_to_origin_monitor,
+        no_focus: true,
+        ..Default::default()
+    };
+    restore_specific_window(address, &options)?;
+    notify(
+        "Window restored",
+        &format!("{} is back, its --ttl ran out", window.class),
+    );
+    Ok(())
+}
+
+/// Folds the entry a window already had into its new one, so minimizing a
+/// tracked window refreshes its record instead of adding a second. While
+/// the old entry still counts as minimized, a group or note it had carries
+/// over unless the new one sets its own. A thumbnail the new entry didn't
+/// replace is deleted rather than left showing an older window.
+fn refresh_entry(window: &mut MinimizedWindow, old: MinimizedWindow) {
+    log_info(&format!(
+        "minimize: address={} class={} was tracked already, refreshing its entry",
+        window.address, window.class
+    ));
+    if !old.restored {
+        window.group = window.group.take().or(old.group);
+        window.note = window.note.take().or(old.note);
+    }
+    if let Some(preview) = old.preview_path.filter(|p| !p.is_empty()) {
+        if window.preview_path.as_deref() != Some(preview.as_str()) {
+            let _ = fs::remove_file(preview);
+        }
+    }
+}
+
+fn refuse_over_limit(address: &str) {
+    let max = config().max_windows;
+    log_error(&format!(
+        "minimize: refusing address={}, already {} windows minimized (max_windows)",
+        address, max
+    ));
+    set_warning(&format!("Minimized window limit ({}) reached", max));
+}
+
+/// Lets the user click a window with `slurp -p` and maps the point back to
+/// the client under it. Floating windows sit above tiled ones, so they win
+/// when both contain the point. `None` if the selection was cancelled or
+/// hit no window.
+fn select_window() -> Option<Client> {
+    let output = timed("slurp", || Command::new("slurp").arg("-p").output());
+    let output = match output {
+        Ok(output) if output.status.success() => output,
+        Ok(_) => return None,
+        Err(e) => {
+            log_error(&format!("minimize: failed to run slurp — {}", e));
+            return None;
+        }
+    };
+
+    // slurp prints the point as `X,Y 1x1`
+    let text = String::from_utf8_lossy(&output.stdout);
+    let (x, y) = text.split_whitespace().next()?.split_once(',')?;
+    let (x, y): (i64, i64) = (x.parse().ok()?, y.parse().ok()?);
+
+    let visible: HashSet<i64> = monitors().iter().map(|m| m.active_workspace).collect();
+    clients()?
+        .into_iter()
+        .filter(|c| visible.contains(&c.workspace_id))
+        .filter(|c| c.rect.is_some_and(|r| r.contains(x, y)))
+        .max_by_key(|c| c.floating)
+}
+
+fn minimize_window(options: &MinimizeOptions) -> Result<(), io::Error> {
+    if let Some(address) = &options.address {
+        return minimize_address(address, options);
+    }
+    if options.class.is_some() || options.title_match.is_some() {
+        return minimize_matching(
+            options.class.as_deref(),
+            options.title_match.as_ref(),
+            options.all_workspaces,
+            options,
+        );
+    }
+    let selected = if options.select {
+        select_window()
+    } else {
+        None
+    };
+    let client = match selected {
+        Some(client) => client,
+        // Cancelled selections fall back to the focused window
+        None => match backend().active_window("minimize")? {
+            Some(client) => client,
+            None => return Ok(()),
+        },
+    };
+
+    // Don't minimize the picker itself
+    if client.class.eq_ignore_ascii_case(&picker_spec().class) {
+        return Ok(());
+    }
+
+    minimize_client(&client, options)
+}
+
+/// Minimizes the window at `address`, wherever it is. A window on a
+/// workspace that isn't shown gets no thumbnail, since grim can only
+/// capture what's on screen.
+fn minimize_address(address: &str, options: &MinimizeOptions) -> io::Result<()> {
+    let Some(live) = clients() else {
+        return Err(io::Error::other(
+            "could not list Hyprland clients; see the log",
+        ));
+    };
+    let Some(client) = live.into_iter().find(|c| c.address == address) else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no window with address {}", address),
+        ));
+    };
+    if backend().is_hidden(&client.workspace_name) {
+        let _lock = lock_state()?;
+        let mut state = load_state()?;
+        if let Some(entry) = state
+            .iter_mut()
+            .find(|w| w.address == address && !w.restored)
+        {
+            // Nothing to move or capture; just bring the entry up to date
+            entry.original_title = client.title.clone();
+            entry.display_title = display_title(&client, &entry.icon);
+            entry.group = options.group.clone().or(entry.group.take());
+            entry.note = options.note.clone().or(entry.note.take());
+            if let Some(ttl) = options.ttl {
+                entry.restore_at = Some(unix_now() + ttl.as_secs());
+            }
+            let timer = options.ttl.and(entry.restore_at);
+            save_minimized_windows(&state)?;
+            if let Some(at) = timer {
+                start_expiry_timer(address, at);
+            }
+            say(&format!("{} is already minimized", address));
+            return Ok(());
+        }
+    }
+
+    let visible = monitors()
+        .iter()
+        .any(|m| m.active_workspace == client.workspace_id);
+    let options = MinimizeOptions {
+        skip_preview: options.skip_preview || !visible,
+        ..options.clone()
+    };
+    minimize_client(&client, &options)
+}
+
+/// Minimizes one known window: records it, moves it to the hidden workspace and
+/// saves the entry once the move succeeded.
+fn minimize_client(client: &Client, options: &MinimizeOptions) -> io::Result<()> {
+    if never_minimize(client) {
+        refuse_protected(client);
+        return Ok(());
+    }
+    if remaining_capacity()? == 0 {
+        refuse_over_limit(&client.address);
+        return Ok(());
+    }
+
+    let live = if client.floating { None } else { clients() };
+    let window = record_window(client, live.as_deref(), &monitors(), options);
+
+    // The visible variant follows the window onto the special workspace so
+    // Hyprland animates the move; it's hidden again once the cache is saved
+    if !move_to_special(&window, config().minimize_visible)? {
+        return Err(io::Error::other(format!(
+            "could not minimize {} ({}); see {}",
+            window.address,
+            window.class,
+            paths().log_file.display()
+        )));
+    }
+
+    let workspace = window.hidden_on.clone().unwrap_or_default();
+    store_minimized(vec![window])?;
+    play_sound(config().sound_minimize.as_deref());
+    if config().minimize_visible {
+        thread::sleep(Duration::from_millis(VISIBLE_MINIMIZE_MS));
+        backend().leave_hidden("minimize", &workspace)?;
+    }
+
+    Ok(())
+}
+
+/// The workspace currently shown on the monitor called `name`.
+fn monitor_workspace(name: &str) -> io::Result<i64> {
+    find_monitor(name).map(|monitor| monitor.active_workspace)
+}
+
+fn find_monitor(name: &str) -> io::Result<Monitor> {
+    let mut monitors = monitors();
+    match monitors.iter().position(|m| m.name == name) {
+        Some(i) => Ok(monitors.swap_remove(i)),
+        None => {
+            let known: Vec<&str> = monitors.iter().map(|m| m.name.as_str()).collect();
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "unknown monitor {:?} (known: {})",
+                    name,
+                    if known.is_empty() {
+                        "none, is Hyprland running?".to_string()
+                    } else {
+                        known.join(", ")
+                    }
+                ),
+            ))
+        }
+    }
+}
+
+/// Minimizes every window on the active workspace (`keep_focused` spares
+/// the focused one) except the picker and excluded classes. Previews are
+/// all captured before anything moves, since each move reflows the layout.
+fn minimize_workspace(
+    options: &MinimizeOptions,
+    keep_focused: bool,
+    exclude: &[String],
+    monitor: Option<&str>,
+) -> io::Result<()> {
+    let context = if keep_focused {
+        "minimize-others"
+    } else {
+        "minimize-all"
+    };
+    let workspace_id = match monitor {
+        Some(name) => Some(monitor_workspace(name)?),
+        None => backend().active_workspace(context)?,
+    };
+    let Some(workspace_id) = workspace_id else {
+        return Ok(());
+    };
+    let focused = if keep_focused {
+        match backend().active_window(context)? {
+            Some(window) => window.address,
+            None => return Ok(()),
+        }
+    } else {
+        String::new()
+    };
+    let Some(live) = clients() else {
+        return Ok(());
+    };
+
+    let picker_class = picker_spec().class;
+    let targets: Vec<&Client> = live
+        .iter()
+        .filter(|c| c.workspace_id == workspace_id && !c.address.is_empty())
+        .filter(|c| c.address != focused)
+        .filter(|c| !c.class.eq_ignore_ascii_case(&picker_class))
+        .filter(|c| !is_excluded(&c.class, exclude) && !never_minimize(c))
+        .collect();
+
+    let minimized = minimize_clients(context, targets, &live, options)?;
+    if keep_focused {
+        let addresses: String = minimized.iter().map(|a| format!("{}\n", a)).collect();
+        write_atomic(&paths().others_file, &addresses)?;
+    }
+    Ok(())
+}
+
+/// Minimizes every window whose class contains `class` (case-insensitive,
+/// like `list --filter`) and whose title matches `title_match`, whichever
+/// are given, on the active workspace, or on every workspace with
+/// `all_workspaces`. Windows that are hidden already are left as they are.
+fn minimize_matching(
+    class: Option<&str>,
+    title_match: Option<&Pattern>,
+    all_workspaces: bool,
+    options: &MinimizeOptions,
+) -> io::Result<()> {
+    let context = "minimize";
+    let workspace_id = if all_workspaces {
+        None
+    } else {
+        match backend().active_workspace(context)? {
+            Some(id) => Some(id),
+            None => return Ok(()),
+        }
+    };
+    let Some(live) = clients() else {
+        return Err(io::Error::other(
+            "could not list Hyprland clients; see the log",
+        ));
+    };
+
+    let needle = class.map(str::to_lowercase);
+    let picker_class = picker_spec().class;
+    let targets: Vec<&Client> = live
+        .iter()
+        .filter(|c| !c.address.is_empty() && !backend().is_hidden(&c.workspace_name))
+        .filter(|c| workspace_id.is_none_or(|id| c.workspace_id == id))
+        .filter(|c| {
+            needle
+                .as_ref()
+                .is_none_or(|n| c.class.to_lowercase().contains(n.as_str()))
+                && title_match.is_none_or(|p| p.is_match(&c.title))
+        })
+        .filter(|c| !c.class.eq_ignore_ascii_case(&picker_class) && !never_minimize(c))
+        .collect();
+    if targets.is_empty() {
+        let what = match (class, title_match) {
+            (Some(class), None) => format!("of class {:?}", class),
+            (None, _) => "with a matching title".to_string(),
+            (Some(class), Some(_)) => format!("of class {:?} with a matching title", class),
+        };
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "no window {} on {}",
+                what,
+                if all_workspaces {
+                    "any workspace"
+                } else {
+                    "the active workspace"
+                }
+            ),
+        ));
+    }
+
+    minimize_clients(context, targets, &live, options)?;
+    Ok(())
+}
+
+/// Minimizes every window in `addresses` together, the way `minimize-all`
+/// does a workspace. An address Hyprland doesn't know is an error before
+/// anything moves; windows that are hidden already are skipped.
+fn minimize_addresses(addresses: &[String], options: &MinimizeOptions) -> io::Result<()> {
+    let Some(live) = clients() else {
+        return Err(io::Error::other(
+            "could not list Hyprland clients; see the log",
+        ));
+    };
+    if let Some(unknown) = addresses
+        .iter()
+        .find(|a| !live.iter().any(|c| &c.address == *a))
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no window with address {}", unknown),
+        ));
+    }
+
+    let picker_class = picker_spec().class;
+    let targets: Vec<&Client> = live
+        .iter()
+        .filter(|c| addresses.contains(&c.address) && !backend().is_hidden(&c.workspace_name))
+        .filter(|c| !c.class.eq_ignore_ascii_case(&picker_class) && !never_minimize(c))
+        .collect();
+    minimize_clients("minimize", targets, &live, options)?;
+    Ok(())
+}
+
+/// Minimizes `targets` (taken from `live`) in one go: previews first, then
+/// the moves as one batch where the backend can, then a single state write.
+/// Windows on workspaces that aren't shown get no thumbnail. Returns the
+/// addresses that were minimized.
+fn minimize_clients(
+    context: &str,
+    mut targets: Vec<&Client>,
+    live: &[Client],
+    options: &MinimizeOptions,
+) -> io::Result<Vec<String>> {
+    let capacity = remaining_capacity()?;
+    if targets.len() > capacity {
+        for client in &targets[capacity..] {
+            refuse_over_limit(&client.address);
+        }
+        targets.truncate(capacity);
+    }
+
+    let monitors = monitors();
+    let visible: HashSet<i64> = monitors.iter().map(|m| m.active_workspace).collect();
+    let unseen = MinimizeOptions {
+        skip_preview: true,
+        ..options.clone()
+    };
+    let records: Vec<MinimizedWindow> = targets
+        .iter()
+        .map(|client| {
+            let options = if visible.contains(&client.workspace_id) {
+                options
+            } else {
+                &unseen
+            };
+            record_window(client, Some(live), &monitors, options)
+        })
+        .collect();
+
+    let total = records.len();
+    let hidden: Vec<(&str, &str)> = records
+        .iter()
+        .map(|w| {
+            (
+                w.address.as_str(),
+                w.hidden_on.as_deref().unwrap_or_default(),
+            )
+        })
+        .collect();
+    // Unpinned ahead of the batch; a window the batch doesn't hide goes
+    // through `move_to_special`, which pins it again if that fails too
+    let batched = total > 1 && {
+        let unpinned = records
+            .iter()
+            .filter(|w| w.pinned)
+            .try_for_each(|w| backend().unpin(context, &w.address).map(drop))
+            .and_then(|()| backend().hide_all(context, &hidden));
+        if unpinned.is_err() {
+            records.iter().for_each(|w| repin(context, w));
+        }
+        unpinned?
+    };
+
+    let mut minimized = Vec::new();
+    for window in records {
+        if batched || move_to_special(&window, false)? {
+            minimized.push(window);
+            progress(&format!(
+                "Minimized {} of {}: {}",
+                minimized.len(),
+                total,
+                minimized[minimized.len() - 1].display_title
+            ));
+        }
+    }
+
+    let addresses = minimized.iter().map(|w| w.address.clone()).collect();
+    if !minimized.is_empty() {
+        store_minimized(minimized)?;
+        play_sound(config().sound_minimize.as_deref());
+    }
+    Ok(addresses)
+}
+
+/// Hides what's on the active workspace into `hide` and brings back the
+/// `show` group in its place. Hiding happens first so the windows that come
+/// back aren't swept up with the rest.
+fn swap_group(show: &str, hide: &str) -> io::Result<()> {
+    if show == hide {
+        return Ok(());
+    }
+    // One lock for both halves, so no other omaveil sees the swap half done
+    let _lock = lock_state()?;
+    let options = MinimizeOptions {
+        capture_delay_ms: config().capture_delay_ms,
+        group: Some(hide.to_string()),
+        ..Default::default()
+    };
+    minimize_workspace(&options, false, &[], None)?;
+    restore_all_windows(&RestoreOptions::default(), Some(show), None, false)
+}
+
+/// Deletes thumbnails in the preview directory that no state entry points at
+/// (left behind by crashes, restores or edited state). Returns how many files
+/// were removed and how many bytes that freed.
+fn gc_previews() -> io::Result<(usize, u64)> {
+    let mut removed = 0;
+    let mut freed = 0;
+    for (path, size) in orphan_previews()? {
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                removed += 1;
+                freed += size;
+            }
+            Err(e) => log_error(&format!(
+                "trim-previews: could not remove {} — {}",
+                path.display(),
+                e
+            )),
+        }
+    }
+
+    Ok((removed, freed))
+}
+
+/// Files in the preview directory no state entry points at, with their sizes.
+fn orphan_previews() -> io::Result<Vec<(PathBuf, u64)>> {
+    // `undo` brings the last restored window back with its thumbnail
+    let last_restored = fs::read_to_string(&paths().last_restored_file)
+        .ok()
+        .and_then(|content| parse_windows_from_json(&content).ok())
+        .unwrap_or_default();
+    let referenced: HashSet<String> = load_state()?
+        .into_iter()
+        .chain(last_restored)
+        .filter_map(|w| w.preview_path)
+        .filter(|p| !p.is_empty())
+        .collect();
+
+    let entries = match fs::read_dir(&paths().preview_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    Ok(entries
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            let metadata = entry.metadata().ok()?;
+            (metadata.is_file() && !referenced.contains(path.to_string_lossy().as_ref()))
+                .then_some((path, metadata.len()))
+        })
+        .collect())
+}
+
+fn trim_previews() -> io::Result<()> {
+    let (removed, freed) = gc_previews()?;
+    say(&format!(
+        "Removed {} orphaned preview(s), freed {:.1} KiB",
+        removed,
+        freed as f64 / 1024.0
+    ));
+    Ok(())
+}
+
+/// Composites every available thumbnail into one labelled contact sheet
+/// with ImageMagick's `montage`. Windows without a thumbnail are skipped.
+fn export_grid(output_path: &str, columns: u64) -> io::Result<()> {
+    let windows: Vec<MinimizedWindow> = load_minimized_windows()?
+        .into_iter()
+        .filter(|w| {
+            w.preview_path
+                .as_deref()
+                .is_some_and(|p| !p.is_empty() && Path::new(p).is_file())
+        })
+        .collect();
+
+    if windows.is_empty() {
+        eprintln!("No minimized windows have previews; nothing to export");
+        return Ok(());
+    }
+
+    let mut args: Vec<String> = Vec::new();
+    for window in &windows {
+        // montage expands %-escapes in labels
+        let label = format!("{} - {}", window.class, window.original_title).replace('%', "%%");
+        args.push("-label".to_string());
+        args.push(label);
+        args.push(window.preview_path.clone().unwrap_or_default());
+    }
+    args.extend(
+        [
+            "-tile",
+            &format!("{}x", columns),
+            "-geometry",
+            "+8+8",
+            "-background",
+            "none",
+            output_path,
+        ]
+        .map(String::from),
+    );
+
+    let output =
+        timed("montage", || Command::new("montage").args(&args).output()).map_err(|e| {
+            log_error(&format!("export-grid: failed to run montage — {}", e));
+            e
+        })?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        log_error(&format!("export-grid: montage failed — {}", stderr));
+        return Err(io::Error::other(format!("montage failed: {}", stderr)));
+    }
+
+    say(&format!(
+        "Wrote {} preview(s) to {}",
+        windows.len(),
+        output_path
+    ));
+    Ok(())
+}
+
+/// Prints minimized windows, optionally narrowed to a class substring and/or
+/// a title regex. Returns whether anything matched so the caller can turn it
+/// into an exit status for scripts.
+fn list_windows(
+    class_filter: Option<&str>,
+    title_match: Option<&Pattern>,
+    as_json: bool,
+) -> io::Result<bool> {
+    let class_filter = class_filter.map(str::to_lowercase);
+    let windows: Vec<MinimizedWindow> = load_minimized_windows()?
+        .into_iter()
+        .filter(|w| {
+            class_filter
+                .as_ref()
+                .is_none_or(|f| w.class.to_lowercase().contains(f.as_str()))
+        })
+        .filter(|w| title_match.is_none_or(|p| p.is_match(&w.original_title)))
+        .collect();
+
+    if as_json {
+        println!("{}", create_json_output(&windows));
+    } else {
+        for window in &windows {
+            match &window.note {
+                Some(note) => println!(
+                    "{}\t{}\t{}\t{}",
+                    window.address, window.class, window.original_title, note
+                ),
+                None => println!(
+                    "{}\t{}\t{}",
+                    window.address, window.class, window.original_title
+                ),
+            }
+        }
+    }
+
+    Ok(!windows.is_empty())
+}
+
+/// Prints the Waybar module JSON. `display` picks what goes in `text`:
+/// "icon", "count", or both.
+fn show_status(display: &str) -> io::Result<()> {
+    println!("{}", status_json(display)?);
+    Ok(())
+}
+
+fn status_json(display: &str) -> io::Result<String> {
+    let windows = load_minimized_windows()?;
+    let count = windows.len();
+
+    let max = config().max_windows;
+    let warning = if max > 0 && count >= max {
+        Some(format!("Minimized window limit ({}) reached", max))
+    } else {
+        fs::read_to_string(&paths().warning_file)
+            .ok()
+            .map(|reason| reason.trim().to_string())
+            .filter(|reason| !reason.is_empty())
+    };
+
+    let text = match display {
+        "icon" => "󰘸".to_string(),
+        "count" => count.to_string(),
+        _ if count > 0 => format!("󰘸 {}", count),
+        _ => "󰘸".to_string(),
+    };
+    let mut tooltip = if count > 0 {
+        format!("{} minimized windows", count)
+    } else {
+        "No minimized windows".to_string()
+    };
+    for window in windows.iter().filter(|w| w.note.is_some()) {
+        tooltip.push_str(&format!(
+            "\\n{}: {}",
+            json::escape(&window.class),
+            json::escape(window.note.as_deref().unwrap_or_default())
+        ));
+    }
+
+    Ok(match warning {
+        Some(reason) => format!(
+            "{{\"text\":\"{}\",\"class\":\"warning\",\"tooltip\":\"{}\\n{} (see {})\"}}",
+            text,
+            tooltip,
+            json::escape(&reason),
+            json::escape(&paths().log_file.display().to_string())
+        ),
+        None if count > 0 => format!(
+            "{{\"text\":\"{}\",\"class\":\"has-windows\",\"tooltip\":\"{}\"}}",
+            text, tooltip
+        ),
+        None => format!(
+            "{{\"text\":\"{}\",\"class\":\"empty\",\"tooltip\":\"{}\"}}",
+            text, tooltip
+        ),
+    })
+}
+
+/// Where `serve` listens unless `--socket` says otherwise.
+fn default_socket_path() -> PathBuf {
+    paths().cache_dir.join("omaveil.sock")
+}
+
+/// Follows Hyprland's event stream and forgets minimized windows as soon as
+/// they close, so dead entries never linger in the picker. Returns when the
+/// stream ends, e.g. because Hyprland exited.
+fn watch() -> io::Result<()> {
+    if backend().name() != "Hyprland" {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "watch follows Hyprland's event socket; not available on {}",
+                backend().name()
+            ),
+        ));
+    }
+    let events = hypr::events()?;
+    log_info("watch: following Hyprland events");
+    if let Err(e) = archive_stale_state() {
+        log_error(&format!("state: could not archive stale state — {}", e));
+    }
+
+    // Read on a thread of its own, so idle windows get minimized even while
+    // no events come in
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in events.lines() {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut idle = IdleTracker::start()?;
+    let mut sweep = WorkspaceSweep::start()?;
+    loop {
+        let line = match &idle {
+            Some(_) => match receiver.recv_timeout(IDLE_CHECK) {
+                Ok(line) => Some(line),
+                Err(mpsc::RecvTimeoutError::Timeout) => None,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            },
+            None => match receiver.recv() {
+                Ok(line) => Some(line),
+                Err(_) => break,
+            },
+        };
+        if let Some(line) = line {
+            let line = line?;
+            // Event addresses come without the 0x prefix the state uses
+            if let Some(address) = line.strip_prefix("closewindow>>") {
+                forget_closed_window(&format!("0x{}", address.trim()))?;
+            }
+            if let (Some(idle), Some(address)) = (&mut idle, line.strip_prefix("activewindowv2>>"))
+            {
+                idle.focused(address.trim());
+            }
+            // `workspacev2>>ID,NAME`
+            let switched = line
+                .strip_prefix("workspacev2>>")
+                .and_then(|data| data.split(',').next()?.trim().parse().ok());
+            if let (Some(sweep), Some(workspace)) = (&mut sweep, switched) {
+                if let Err(e) = sweep.switched(workspace) {
+                    log_error(&format!("watch: workspace sweep failed — {}", e));
+                }
+            }
+        }
+        if let Some(idle) = &mut idle {
+            // A failed sweep is retried on the next one rather than ending `watch`
+            if let Err(e) = idle.sweep() {
+                log_error(&format!("watch: idle minimize failed — {}", e));
+            }
+        }
+    }
+    log_info("watch: event stream ended");
+    Ok(())
+}
+
+/// When each window last had focus, for `idle_minimize_minutes`. Windows
+/// start their clock when `watch` first sees them, and a hidden window's
+/// clock is held at zero so it doesn't come back already idle.
+struct IdleTracker {
+    timeout: Duration,
+    last_focus: HashMap<String, Instant>,
+    focused: Option<String>,
+    last_sweep: Instant,
+}
+
+impl IdleTracker {
+    /// `None` when idle minimizing is off.
+    fn start() -> io::Result<Option<IdleTracker>> {
+        let minutes = config().idle_minimize_minutes;
+        if minutes == 0 {
+            return Ok(None);
+        }
+        log_info(&format!(
+            "watch: minimizing windows left unfocused for {} min",
+            minutes
+        ));
+        Ok(Some(IdleTracker {
+            timeout: Duration::from_secs(minutes * 60),
+            last_focus: HashMap::new(),
+            focused: backend().active_window("watch")?.map(|c| c.address),
+            last_sweep: Instant::now(),
+        }))
+    }
+
+    /// Handles `activewindowv2`, whose address lacks the 0x prefix and is
+    /// empty when nothing has focus.
+    fn focused(&mut self, address: &str) {
+        let now = Instant::now();
+        if let Some(previous) = self.focused.take() {
+            self.last_focus.insert(previous, now);
+        }
+        if !address.is_empty() {
+            let address = format!("0x{}", address);
+            self.last_focus.insert(address.clone(), now);
+            self.focused = Some(address);
+        }
+    }
+
+    /// Minimizes the windows that have been idle too long, at most once per
+    /// `IDLE_CHECK`.
+    fn sweep(&mut self) -> io::Result<()> {
+        if self.last_sweep.elapsed() < IDLE_CHECK {
+            return Ok(());
+        }
+        self.last_sweep = Instant::now();
+        let Some(live) = clients() else {
+            return Ok(());
+        };
+
+        let now = Instant::now();
+        self.last_focus
+            .retain(|address, _| live.iter().any(|c| &c.address == address));
+        for client in &live {
+            if backend().is_hidden(&client.workspace_name) {
+                self.last_focus.insert(client.address.clone(), now);
+            } else {
+                self.last_focus.entry(client.address.clone()).or_insert(now);
+            }
+        }
+
+        let picker_class = picker_spec().class;
+        let targets: Vec<&Client> = live
+            .iter()
+            .filter(|c| !c.address.is_empty() && !backend().is_hidden(&c.workspace_name))
+            .filter(|c| self.focused.as_deref() != Some(c.address.as_str()))
+            .filter(|c| idle_minimize_applies(&c.class))
+            .filter(|c| !c.class.eq_ignore_ascii_case(&picker_class) && !never_minimize(c))
+            .filter(|c| now.duration_since(self.last_focus[&c.address]) >= self.timeout)
+            .collect();
+        if targets.is_empty() {
+            return Ok(());
+        }
+        for client in &targets {
+            log_info(&format!(
+                "watch: minimizing idle address={} class={}",
+                client.address, client.class
+            ));
+        }
+        let options = MinimizeOptions {
+            capture_delay_ms: config().capture_delay_ms,
+            ..Default::default()
+        };
+        minimize_clients("watch", targets, &live, &options)?;
+        Ok(())
+    }
+}
+
+/// The `sweep_on_leave` rule: windows of the listed classes are minimized
+/// off a workspace when it's switched away from, into a group named after
+/// it, and restored when it's switched back to.
+struct WorkspaceSweep {
+    current: Option<i64>,
+}
+
+impl WorkspaceSweep {
+    /// `None` when `sweep_on_leave` is empty.
+    fn start() -> io::Result<Option<WorkspaceSweep>> {
+        if config().sweep_on_leave.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(WorkspaceSweep {
+            current: backend().active_workspace("watch")?,
+        }))
+    }
+
+    fn group(workspace: i64) -> String {
+        format!("workspace-{}", workspace)
+    }
+
+    fn switched(&mut self, workspace: i64) -> io::Result<()> {
+        let Some(left) = self
+            .current
+            .replace(workspace)
+            .filter(|&id| id != workspace)
+        else {
+            return Ok(());
+        };
+        // Special workspaces come and go on top; only regular ones count
+        if left > 0 && !monitors().iter().any(|m| m.active_workspace == left) {
+            self.hide(left)?;
+        }
+        if workspace > 0 {
+            self.bring_back(workspace)?;
+        }
+        Ok(())
+    }
+
+    fn hide(&self, workspace: i64) -> io::Result<()> {
+        let Some(live) = clients() else {
+            return Ok(());
+        };
+        let targets: Vec<&Client> =
+            live.iter()
+                .filter(|c| c.workspace_id == workspace && !c.address.is_empty())
+                .filter(|c| {
+                    let class = c.class.to_lowercase();
+                    config().sweep_on_leave.iter().any(|pattern| {
+                        !pattern.is_empty() && class.contains(&pattern.to_lowercase())
+                    })
+                })
+                .filter(|c| !never_minimize(c))
+                .collect();
+        if targets.is_empty() {
+            return Ok(());
+        }
+        log_info(&format!(
+            "watch: sweeping {} window(s) off workspace {}",
+            targets.len(),
+            workspace
+        ));
+        let options = MinimizeOptions {
+            group: Some(WorkspaceSweep::group(workspace)),
+            ..Default::default()
+        };
+        minimize_clients("watch", targets, &live, &options)?;
+        Ok(())
+    }
+
+    fn bring_back(&self, workspace: i64) -> io::Result<()> {
+        let group = WorkspaceSweep::group(workspace);
+        if !load_minimized_windows()?
+            .iter()
+            .any(|w| w.group.as_deref() == Some(group.as_str()))
+        {
+            return Ok(());
+        }
+        log_info(&format!(
+            "watch: restoring what was swept off workspace {}",
+            workspace
+        ));
+        let options = RestoreOptions {
+            no_focus: true,
+            ..Default::default()
+        };
+        restore_all_windows(&options, Some(&group), None, false)
+    }
+}
+
+/// Whether idle minimizing covers `class`: it's on `idle_minimize_classes`
+/// (or that list is empty) and not on `idle_minimize_exclude`.
+fn idle_minimize_applies(class: &str) -> bool {
+    let class = class.to_lowercase();
+    let listed = |patterns: &[String]| {
+        patterns
+            .iter()
+            .any(|pattern| !pattern.is_empty() && class.contains(&pattern.to_lowercase()))
+    };
+    (config().idle_minimize_classes.is_empty() || listed(&config().idle_minimize_classes))
+        && !listed(&config().idle_minimize_exclude)
+}
+
+/// Drops the state entry and thumbnail of a window that has closed, if it
+/// was minimized.
+fn forget_closed_window(address: &str) -> io::Result<()> {
+    let _lock = lock_state()?;
+    let mut state = load_state()?;
+    let Some(i) = state.iter().position(|w| w.address == address) else {
+        return Ok(());
+    };
+    let window = state.remove(i);
+    if let Some(preview) = window.preview_path.as_deref().filter(|p| !p.is_empty()) {
+        let _ = fs::remove_file(preview);
+    }
+    save_minimized_windows(&state)?;
+    log_info(&format!(
+        "watch: dropped address={} class={} (window closed)",
+        window.address, window.class
+    ));
+    Ok(())
+}
+
+/// Runs OmaVeil as a long-lived backend on a Unix socket. Each line a client
+/// sends is a JSON request like `{"cmd":"restore","addr":"0x..."}` and gets
+/// one JSON line back. Connections are served one at a time, so requests
+/// never race each other on the state file. `{"cmd":"shutdown"}` stops the
+/// server and removes the socket.
+fn serve(socket: &Path) -> io::Result<()> {
+    if socket.exists() {
+        // Another server still answering means we shouldn't steal its socket
+        if UnixStream::connect(socket).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is already being served", socket.display()),
+            ));
+        }
+        fs::remove_file(socket)?;
+    }
+    let listener = UnixListener::bind(socket)?;
+    log_info(&format!("serve: listening on {}", socket.display()));
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log_error(&format!("serve: accept failed — {}", e));
+                continue;
+            }
+        };
+        match serve_connection(stream) {
+            Ok(true) => break,
+            Ok(false) => {}
+            Err(e) => log_error(&format!("serve: connection dropped — {}", e)),
+        }
+    }
+
+    let _ = fs::remove_file(socket);
+    log_info("serve: shut down");
+    Ok(())
+}
+
+/// Answers requests on one connection until the client hangs up. Returns
+/// true if the client asked the server to shut down.
+fn serve_connection(stream: UnixStream) -> io::Result<bool> {
+    let mut writer = stream.try_clone()?;
+    for line in io::BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (response, shutdown) = match serde_json::from_str::<json::Value>(&line) {
+            Ok(request) => {
+                let cmd = request.str_field("cmd");
+                match handle_request(&request) {
+                    Ok(Some(payload)) => (format!("{{\"ok\":true,{}}}", payload), false),
+                    Ok(None) => ("{\"ok\":true}".to_string(), cmd == "shutdown"),
+                    Err(e) => (error_response(&e.to_string()), false),
+                }
+            }
+            Err(e) => (error_response(&format!("invalid request: {}", e)), false),
+        };
+        writeln!(writer, "{}", response)?;
+        if shutdown {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn error_response(message: &str) -> String {
+    format!("{{\"ok\":false,\"error\":\"{}\"}}", json::escape(message))
+}
+
+/// Runs one `serve` request through the same functions the CLI uses.
+/// Returns extra `"key":value` JSON to include in the reply, if any.
+fn handle_request(request: &json::Value) -> io::Result<Option<String>> {
+    // Feedback sounds play once per command, not once per server lifetime
+    SOUND_PLAYED.store(false, Ordering::Relaxed);
+
+    let group = match request.str_field("group") {
+        "" => None,
+        group => Some(validate_group(group)?),
+    };
+    match request.str_field("cmd") {
+        "minimize" => {
+            let options = MinimizeOptions {
+                capture_delay_ms: config().capture_delay_ms,
+                group,
+                address: Some(request.str_field("addr").to_string()).filter(|a| !a.is_empty()),
+                note: Some(request.str_field("note").to_string()).filter(|n| !n.is_empty()),
+                ttl: match request.str_field("ttl") {
+                    "" => None,
+                    ttl => Some(parse_duration(ttl)?),
+                },
+                ..Default::default()
+            };
+            minimize_window(&options)?;
+            Ok(None)
+        }
+        "restore" => {
+            maybe_prune(false)?;
+            match request.str_field("addr") {
+                "" => show_restore_menu(&RestoreOptions::default())?,
+                addr => restore_specific_window(addr, &RestoreOptions::default())?,
+            }
+            Ok(None)
+        }
+        "restore-all" => {
+            maybe_prune(false)?;
+            let class = Some(request.str_field("class")).filter(|c| !c.is_empty());
+            restore_all_windows(&RestoreOptions::default(), group.as_deref(), class, false)?;
+            Ok(None)
+        }
+        "list" => Ok(Some(format!(
+            "\"windows\":{}",
+            create_json_output(&load_minimized_windows()?)
+        ))),
+        "show" => Ok(Some(format!("\"status\":{}", status_json("both")?))),
+        "shutdown" => Ok(None),
+        "" => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "request has no \"cmd\"",
+        )),
+        other => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown cmd {:?}", other),
+        )),
+    }
+}
+
+/// Whether `program` resolves to an executable: either a path as given, or
+/// a name found in one of the `$PATH` directories.
+fn find_in_path(program: &str) -> bool {
+    if program.contains('/') {
+        return Path::new(program).is_file();
+    }
+    env::var_os("PATH")
+        .map(|path| env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+}
+
+/// Checks a config file without applying it: anything `Config::parse`
+/// rejects is an error, referenced programs or files that don't exist are
+/// warnings. Returns whether the file is free of errors.
+fn validate_config(path: Option<&str>) -> io::Result<bool> {
+    let path = path.map_or_else(Config::path, PathBuf::from);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) => {
+            println!("{}: cannot read — {}", path.display(), e);
+            println!("FAIL");
+            return Ok(false);
+        }
+    };
+
+    let (parsed, errors) = Config::parse(&content);
+    let mut warnings = Vec::new();
+    if let Some(picker) = parsed.picker.split_whitespace().next() {
+        if !find_in_path(picker) {
+            warnings.push(format!("picker `{}` not found in $PATH", picker));
+        }
+    }
+    let sounds = [&parsed.sound_minimize, &parsed.sound_restore];
+    if sounds.iter().any(|s| s.is_some()) {
+        if let Some(player) = parsed.sound_player.split_whitespace().next() {
+            if !find_in_path(player) {
+                warnings.push(format!("sound_player `{}` not found in $PATH", player));
+            }
+        }
+    }
+    for (key, file) in ["sound_minimize", "sound_restore"].iter().zip(sounds) {
+        if let Some(file) = file.as_deref().filter(|f| !Path::new(f).is_file()) {
+            warnings.push(format!("{}: {} does not exist", key, file));
+        }
+    }
+
+    println!("{}", path.display());
+    for error in &errors {
+        println!("  error: {}", error);
+    }
+    for warning in &warnings {
+        println!("  warning: {}", warning);
+    }
+    if errors.is_empty() {
+        println!("OK ({} warning(s))", warnings.len());
+    } else {
+        println!(
+            "FAIL: {} error(s), {} warning(s)",
+            errors.len(),
+            warnings.len()
+        );
+    }
+    Ok(errors.is_empty())
+}
+
+/// One `doctor` finding. A `problem` of `None` means the check passed;
+/// otherwise `repair` runs when fixing and describes what it did, and
+/// anything left unrepaired is collected in `issues`.
+fn doctor_check(
+    fix: bool,
+    issues: &mut Vec<String>,
+    ok: &str,
+    problem: Option<String>,
+    repair: Option<&dyn Fn() -> io::Result<String>>,
+) -> io::Result<()> {
+    let Some(problem) = problem else {
+        println!("ok       {}", ok);
+        return Ok(());
+    };
+    match repair {
+        Some(repair) if fix => println!("fixed    {}", repair()?),
+        Some(_) => {
+            println!("problem  {} (--fix repairs this)", problem);
+            issues.push(problem);
+        }
+        None => {
+            println!("problem  {}", problem);
+            issues.push(problem);
+        }
+    }
+    Ok(())
+}
+
+/// Looks for the usual ways things go wrong: missing state directories, a
+/// broken state file, entries for closed windows, stray thumbnails, hidden
+/// windows the state lost track of, config errors and missing tools. With
+/// `fix`, everything that can be repaired is. Returns whether nothing is
+/// left to do.
+fn doctor(fix: bool) -> io::Result<bool> {
+    let mut issues = Vec::new();
+
+    for dir in [&paths().cache_dir, &paths().preview_dir] {
+        doctor_check(
+            fix,
+            &mut issues,
+            &format!("{} exists", dir.display()),
+            (!dir.is_dir()).then(|| format!("{} is missing", dir.display())),
+            Some(&|| {
+                fs::create_dir_all(dir)?;
+                Ok(format!("created {}", dir.display()))
+            }),
+        )?;
+    }
+
+    let cache_file = &paths().cache_file;
+    let content = fs::read_to_string(cache_file).ok();
+    let state_problem = match content.as_deref().map(str::trim) {
+        None => Some(format!("{} is missing", cache_file.display())),
+        Some("") => Some(format!("{} is empty", cache_file.display())),
+        Some(text) if !(text.starts_with('[') && text.ends_with(']')) => {
+            Some(format!("{} is corrupt", cache_file.display()))
+        }
+        Some(_) => None,
+    };
+    doctor_check(
+        fix,
+        &mut issues,
+        &format!("{} is readable", cache_file.display()),
+        state_problem,
+        Some(&|| {
+            let _lock = lock_state()?;
+            if cache_file.exists() {
+                // Backs up a corrupt file and rewrites an empty one
+                load_state()?;
+            } else {
+                write_atomic(cache_file, "[]")?;
+            }
+            Ok(format!("reset {} to an empty stack", cache_file.display()))
+        }),
+    )?;
+
+    let orphan_files = orphan_previews()?;
+    doctor_check(
+        fix,
+        &mut issues,
+        "every thumbnail belongs to a state entry",
+        (!orphan_files.is_empty())
+            .then(|| format!("{} thumbnail(s) without a state entry", orphan_files.len())),
+        Some(&|| {
+            let (removed, freed) = gc_previews()?;
+            Ok(format!(
+                "removed {} orphaned thumbnail(s), freed {:.1} KiB",
+                removed,
+                freed as f64 / 1024.0
+            ))
+        }),
+    )?;
+
+    match clients() {
+        None => doctor_check(
+            fix,
+            &mut issues,
+            "",
+            Some(format!(
+                "could not list {0} windows; is {0} running?",
+                backend().name()
+            )),
+            None,
+        )?,
+        Some(live) => {
+            if let Some(version) = hypr::version().filter(|_| backend().name() == "Hyprland") {
+                let shims = if version < hypr::Version(0, 38, 0) {
+                    " (older than 0.38; using compatibility fallbacks)"
+                } else {
+                    ""
+                };
+                println!("ok       Hyprland {}{}", version, shims);
+            }
+            let addresses: HashSet<&str> = live.iter().map(|c| c.address.as_str()).collect();
+            let dead = load_state()?
+                .iter()
+                .filter(|w| !addresses.contains(w.address.as_str()))
+                .count();
+            doctor_check(
+                fix,
+                &mut issues,
+                "every state entry has a live window",
+                (dead > 0).then(|| format!("{} state entry(s) for closed windows", dead)),
+                Some(&|| {
+                    Ok(format!(
+                        "dropped {} entry(s) for closed windows",
+                        prune_dead_windows()?
+                    ))
+                }),
+            )?;
+
+            let orphans = orphan_clients(&live)?;
+            doctor_check(
+                fix,
+                &mut issues,
+                "every hidden window is in the state",
+                (!orphans.is_empty()).then(|| {
+                    format!(
+                        "{} window(s) on {} missing from the state",
+                        orphans.len(),
+                        backend().hidden_workspace()
+                    )
+                }),
+                Some(&|| {
+                    Ok(format!(
+                        "added {} orphaned window(s) back to the stack",
+                        adopt_orphans(&orphans)?
+                    ))
+                }),
+            )?;
+        }
+    }
+
+    let config_path = Config::path();
+    if let Ok(content) = fs::read_to_string(&config_path) {
+        let (_, errors) = Config::parse(&content);
+        doctor_check(
+            fix,
+            &mut issues,
+            &format!("{} parses", config_path.display()),
+            (!errors.is_empty()).then(|| {
+                format!(
+                    "{} has {} error(s); see `omaveil config validate`",
+                    config_path.display(),
+                    errors.len()
+                )
+            }),
+            None,
+        )?;
+    }
+
+    let picker = config()
+        .picker
+        .split_whitespace()
+        .next()
+        .unwrap_or_default();
+    // Sway and niri are spoken to over their sockets alone; hyprctl is
+    // Hyprland's fallback transport
+    let compositor_tools: &[&str] = match backend().name() {
+        "Hyprland" => &["hyprctl"],
+        "river" => &["riverctl", "lswt"],
+        "wlroots" => &["lswt", "wlrctl"],
+        _ => &[],
+    };
+    for tool in compositor_tools
+        .iter()
+        .copied()
+        .chain(["grim", "convert", picker])
+    {
+        doctor_check(
+            fix,
+            &mut issues,
+            &format!("{} found", tool),
+            (!find_in_path(tool)).then(|| format!("{} not found in $PATH", tool)),
+            None,
+        )?;
+    }
+
+    println!();
+    if issues.is_empty() {
+        println!("All good");
+    } else {
+        println!("{} issue(s) left:", issues.len());
+        for issue in &issues {
+            println!("  {}", issue);
+        }
+    }
+    Ok(issues.is_empty())
+}
+
+/// Prints the resolved config as TOML. With `effective`, each line also
+/// says which layer the value came from, to answer "why isn't my setting
+/// applied?".
+fn print_config(effective: bool) {
+    let config = config();
+    if effective {
+        println!("# file: {}", Config::path().display());
+    }
+    for key in config::KEYS {
+        let line = match config.value(key) {
+            Some(value) => format!("{} = {}", key, value),
+            None => format!("# {} is not set", key),
+        };
+        if effective {
+            println!("{:<48} # {}", line, config.source(key).as_str());
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+/// Compares the running version against the latest published release.
+/// Opt-in only; never downloads anything. Any network or parse failure is
+/// reported as "unknown" rather than an error.
+fn version_check() -> io::Result<()> {
+    let current = env!("CARGO_PKG_VERSION");
+
+    let latest = timed("curl", || {
+        Command::new("curl")
+            .args(["-fsSL", "--max-time", "5", &config().release_url])
+            .output()
+    })
+    .map_err(|e| format!("failed to run curl — {}", e))
+    .and_then(|output| {
+        if !output.status.success() {
+            return Err(format!(
+                "curl failed — {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        let release: json::Value =
+            serde_json::from_slice(&output.stdout).map_err(|e| e.to_string())?;
+        match release.str_field("tag_name") {
+            "" => Err("release response has no tag_name".to_string()),
+            tag => Ok(tag.to_string()),
+        }
+    });
+
+    match latest {
+        Ok(tag) => match compare_versions(current, &tag) {
+            Some(std::cmp::Ordering::Less) => {
+                println!("omaveil {}: update available ({})", current, tag)
+            }
+            Some(_) => println!("omaveil {}: up to date (latest {})", current, tag),
+            None => println!("omaveil {}: unknown (unrecognised tag {:?})", current, tag),
+        },
+        Err(e) => {
+            log_error(&format!("version-check: {}", e));
+            println!(
+                "omaveil {}: unknown (could not reach release server)",
+                current
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Compares dotted numeric versions, ignoring a leading `v` on either side.
+fn compare_versions(current: &str, latest: &str) -> Option<std::cmp::Ordering> {
+    let parse = |v: &str| -> Option<Vec<u64>> {
+        v.trim()
+            .trim_start_matches('v')
+            .split('.')
+            .map(|part| part.parse().ok())
+            .collect()
+    };
+    Some(parse(current)?.cmp(&parse(latest)?))
+}
+
+/// Command-line options for a single subcommand. Flags are pulled out by
+/// name as each command asks for them; whatever is left over is positional.
+struct Args {
+    rest: Vec<String>,
+}
+
+impl Args {
+    fn new(args: &[String]) -> Self {
+        Args {
+            rest: args.to_vec(),
+        }
+    }
+
+    /// Removes `name` from the arguments, returning whether it was present.
+    fn flag(&mut self, name: &str) -> bool {
+        let before = self.rest.len();
+        self.rest.retain(|a| a != name);
+        self.rest.len() != before
+    }
+
+    /// Removes `name <value>` (or `name=value`) from the arguments,
+    /// returning the value of the last occurrence.
+    fn value(&mut self, name: &str) -> io::Result<Option<String>> {
+        Ok(self.values(name)?.pop())
+    }
+
+    /// Like `value`, but for repeatable options: every occurrence, in order.
+    fn values(&mut self, name: &str) -> io::Result<Vec<String>> {
+        let mut found = Vec::new();
+        let mut i = 0;
+        while i < self.rest.len() {
+            if self.rest[i] == name {
+                if i + 1 >= self.rest.len() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{} requires a value", name),
+                    ));
+                }
+                found.push(self.rest.remove(i + 1));
+                self.rest.remove(i);
+            } else if let Some(v) = self.rest[i]
+                .strip_prefix(name)
+                .and_then(|rest| rest.strip_prefix('='))
+            {
+                found.push(v.to_string());
+                self.rest.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+        Ok(found)
+    }
+
+    /// Like `value`, parsed as a number.
+    fn number(&mut self, name: &str) -> io::Result<Option<u64>> {
+        self.value(name)?
+            .map(|v| {
+                v.parse().map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{} expects a number, got {:?}", name, v),
+                    )
+                })
+            })
+            .transpose()
+    }
+
+    /// Finishes option parsing, rejecting any unrecognised `--flag`.
+    fn positional(self) -> io::Result<Vec<String>> {
+        if let Some(unknown) = self.rest.iter().find(|a| a.starts_with("--")) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown option {}", unknown),
+            ));
+        }
+        Ok(self.rest)
+    }
+
+    /// Finishes option parsing for a command that takes no operands, so a
+    /// stray argument is an error rather than quietly ignored.
+    fn no_positional(self) -> io::Result<()> {
+        match self.positional()?.first() {
+            Some(extra) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unexpected argument {}", extra),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Runs the pre-restore prune unless disabled for this invocation or in config.
+fn maybe_prune(no_prune: bool) -> io::Result<()> {
+    if !no_prune && config().prune_on_restore {
+        prune_dead_windows()?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let paths = match resolve_paths() {
+        Ok(paths) => paths,
+        Err(e) => {
+            eprintln!("omaveil: {}", e);
+            process::exit(1);
+        }
+    };
+    let _ = PATHS.set(paths);
+    adopt_unscoped_state();
+
+    let mut argv: Vec<String> = env::args().skip(1).collect();
+    let mut global = Args::new(&argv);
+    // Load up front so `log_level` applies to the very first hyprctl call
+    let loaded = global.values("--set").and_then(|mut overrides| {
+        if let Some(name) = global.value("--special-workspace")? {
+            overrides.push(format!("special_workspace={}", name));
+        }
+        Config::load(&overrides).map_err(io::Error::other)
+    });
+    match loaded {
+        Ok(loaded) => {
+            let _ = CONFIG.set(loaded);
+            argv = global.rest;
+        }
+        Err(e) => {
+            eprintln!("omaveil: {}", e);
+            process::exit(1);
+        }
+    }
+    log_info(&format!("start: omaveil {}", argv.join(" ")));
+
+    match take_verbosity(&mut argv) {
+        Ok(verbosity) => {
+            let _ = VERBOSITY.set(verbosity);
+        }
+        Err(e) => {
+            eprintln!("omaveil: {}", e);
+            process::exit(1);
+        }
+    }
+
+    let command = argv.first().cloned().unwrap_or_default();
+    if let Err(e) = timed(&format!("omaveil {}", command), || run(&argv)) {
+        eprintln!("omaveil: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(argv: &[String]) -> io::Result<()> {
+    if !paths().cache_file.exists() {
+        let _lock = lock_state()?;
+        if !paths().cache_file.exists() {
+            write_atomic(&paths().cache_file, "[]")?;
+        }
+    }
+
+    let command = argv.first().map(|s| s.as_str()).unwrap_or("");
+    let mut opts = Args::new(argv.get(1..).unwrap_or_default());
+
+    match command {
+        "minimize" => {
+            let options = MinimizeOptions {
+                capture_delay_ms: opts
+                    .number("--capture-delay")?
+                    .unwrap_or(config().capture_delay_ms),
+                group: opts
+                    .value("--group")?
+                    .map(|g| validate_group(&g))
+                    .transpose()?,
+                select: opts.flag("--select"),
+                skip_preview: opts.flag("--no-preview"),
+                address: opts.value("--address")?,
+                class: opts.value("--class")?,
+                title_match: opts
+                    .value("--title-match")?
+                    .map(|p| {
+                        Pattern::new(&p).map_err(|e| {
+                            io::Error::new(
+                                io::ErrorKind::InvalidInput,
+                                format!("invalid --title-match pattern: {}", e),
+                            )
+                        })
+                    })
+                    .transpose()?,
+                all_workspaces: opts.flag("--all-workspaces"),
+                note: opts.value("--note")?.filter(|n| !n.trim().is_empty()),
+                ttl: opts
+                    .value("--ttl")?
+                    .map(|t| parse_duration(&t))
+                    .transpose()?,
+            };
+            if options.ttl == Some(Duration::ZERO) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "--ttl must be longer than 0s",
+                ));
+            }
+            let from_stdin = opts.flag("--stdin");
+            let matching = options.class.is_some() || options.title_match.is_some();
+            let ways = [
+                options.select,
+                options.address.is_some(),
+                matching,
+                from_stdin,
+            ];
+            if ways.iter().filter(|&&way| way).count() > 1 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "pick the window one way: --select, --address, --class/--title-match or --stdin",
+                ));
+            }
+            if options.all_workspaces && !matching {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "--all-workspaces only applies together with --class or --title-match",
+                ));
+            }
+            opts.no_positional()?;
+            if from_stdin {
+                let addresses: Vec<String> = io::stdin()
+                    .lines()
+                    .map(|line| line.map(|l| l.trim().to_string()))
+                    .filter(|line| line.as_ref().map_or(true, |l| !l.is_empty()))
+                    .collect::<io::Result<_>>()?;
+                minimize_addresses(&addresses, &options)?;
+            } else {
+                minimize_window(&options)?;
+            }
+        }
+        "minimize-all" | "minimize-others" => {
+            let options = MinimizeOptions {
+                capture_delay_ms: opts
+                    .number("--capture-delay")?
+                    .unwrap_or(config().capture_delay_ms),
+                group: opts
+                    .value("--group")?
+                    .map(|g| validate_group(&g))
+                    .transpose()?,
+                skip_preview: opts.flag("--no-preview"),
+                ..Default::default()
+            };
+            let exclude = opts.values("--exclude")?;
+            let monitor = if command == "minimize-all" {
+                opts.value("--monitor")?
+            } else {
+                None
+            };
+            opts.no_positional()?;
+            minimize_workspace(
+                &options,
+                command == "minimize-others",
+                &exclude,
+                monitor.as_deref(),
+            )?;
+        }
+        "restore" => {
+            maybe_prune(opts.flag("--no-prune"))?;
+            let mut options = RestoreOptions {
+                layout_hint: opts.flag("--layout-hint"),
+                keep: opts.flag("--keep"),
+                print_geometry: opts.flag("--print-geometry"),
+                json: opts.flag("--json"),
+                center_on: opts.value("--center-on-monitor")?,
+                origin_monitor: opts.flag("--origin-monitor") || config().restore_to_origin_monitor,
+                no_focus: opts.flag("--no-focus"),
+                into_group: opts.flag("--into-group"),
+                ..Default::default()
+            };
+            check_no_focus(&options)?;
+            if options.into_group && options.center_on.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "--into-group can't be combined with --center-on-monitor",
+                ));
+            }
+            if options.json && !options.print_geometry {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "--json only applies together with --print-geometry",
+                ));
+            }
+            if let Some(name) = &options.center_on {
+                // Fail before the picker opens rather than after
+                find_monitor(name)?;
+            }
+            if let Some(workspace) = opts.value("--workspace")? {
+                if options.center_on.is_some() || options.layout_hint {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "--workspace can't be combined with --center-on-monitor or --layout-hint",
+                    ));
+                }
+                options.workspace = Some(resolve_workspace(&workspace)?);
             }
+            let toggle = opts.value("--toggle")?;
+            let class = opts.value("--class")?;
+            let title_match = opts
+                .value("--title-match")?
+                .map(|p| {
+                    Pattern::new(&p).map_err(|e| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("invalid --title-match pattern: {}", e),
+                        )
+                    })
+                })
+                .transpose()?;
+            let index = opts.number("--index")?;
+            let query = opts.value("--query")?;
+            let group = opts
+                .value("--group")?
+                .map(|g| validate_group(&g))
+                .transpose()?;
+            let first = opts.flag("--first");
+            let all = opts.flag("--all");
+            let addresses = opts.positional()?;
+            let matching = class.is_some() || title_match.is_some();
+            let ways = [
+                toggle.is_some(),
+                matching,
+                index.is_some(),
+                query.is_some(),
+                group.is_some(),
+                !addresses.is_empty(),
+            ];
+            if ways.iter().filter(|given| **given).count() > 1 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "pick the window one way: an address, --toggle, --class/--title-match, --index, --query or --group",
+                ));
+            }
+            if (first || all) && query.is_none() && !matching {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "--first and --all only apply to --class, --title-match and --query",
+                ));
+            }
+            let ambiguity = match (first, all) {
+                (true, true) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "--first and --all are mutually exclusive",
+                    ))
+                }
+                (true, false) => Ambiguity::First,
+                (false, true) => Ambiguity::All,
+                (false, false) => Ambiguity::Pick,
+            };
+            if let Some(target) = toggle {
+                toggle_window(&target, &options)?;
+            } else if let Some(query) = query {
+                let candidates = query_matches(&query)?;
+                restore_candidates(&candidates, ambiguity, &format!("{:?}", query), &options)?;
+            } else if matching {
+                let candidates = class_title_matches(class.as_deref(), title_match.as_ref())?;
+                restore_candidates(&candidates, ambiguity, "the filter", &options)?;
+            } else if let Some(index) = index {
+                restore_index(index as usize, &options)?;
+            } else if let Some(group) = group {
+                restore_group(&group, &options)?;
+            } else if addresses.len() > 1 {
+                restore_addresses(&addresses, &options)?;
+            } else {
+                restore_window(addresses.first().map(String::as_str), &options)?;
+            }
+        }
+        "restore-all" => {
+            maybe_prune(opts.flag("--no-prune"))?;
+            let options = RestoreOptions {
+                layout_hint: opts.flag("--layout-hint"),
+                delay_ms: opts.number("--delay")?.unwrap_or(0),
+                original_workspace: opts.flag("--original-workspaces"),
+                origin_monitor: opts.flag("--origin-monitor") || config().restore_to_origin_monitor,
+                no_focus: opts.flag("--no-focus"),
+                ..Default::default()
+            };
+            check_no_focus(&options)?;
+            let group = opts
+                .value("--group")?
+                .map(|g| validate_group(&g))
+                .transpose()?;
+            let class = opts.value("--class")?;
+            let from_others = opts.flag("--from-others");
+            opts.no_positional()?;
+            restore_all_windows(&options, group.as_deref(), class.as_deref(), from_others)?;
+        }
+        "swap-group" => {
+            let groups = opts.positional()?;
+            let [show, hide] = groups.as_slice() else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "usage: omaveil swap-group <show> <hide>",
+                ));
+            };
+            swap_group(&validate_group(show)?, &validate_group(hide)?)?;
+        }
+        "undo" => {
+            opts.no_positional()?;
+            undo_restore()?;
+        }
+        "toggle" => {
+            maybe_prune(opts.flag("--no-prune"))?;
+            let minimize = MinimizeOptions {
+                capture_delay_ms: opts
+                    .number("--capture-delay")?
+                    .unwrap_or(config().capture_delay_ms),
+                group: opts
+                    .value("--group")?
+                    .map(|g| validate_group(&g))
+                    .transpose()?,
+                skip_preview: opts.flag("--no-preview"),
+                ..Default::default()
+            };
+            let restore = RestoreOptions {
+                origin_monitor: config().restore_to_origin_monitor,
+                ..Default::default()
+            };
+            opts.no_positional()?;
+            toggle_focused(&minimize, &restore)?;
+        }
+        "swap" => {
+            maybe_prune(opts.flag("--no-prune"))?;
+            let minimize = MinimizeOptions {
+                capture_delay_ms: opts
+                    .number("--capture-delay")?
+                    .unwrap_or(config().capture_delay_ms),
+                group: opts
+                    .value("--group")?
+                    .map(|g| validate_group(&g))
+                    .transpose()?,
+                skip_preview: opts.flag("--no-preview"),
+                ..Default::default()
+            };
+            let restore = RestoreOptions {
+                origin_monitor: config().restore_to_origin_monitor,
+                ..Default::default()
+            };
+            opts.no_positional()?;
+            swap_focused(&minimize, &restore)?;
+        }
+        "peek" => {
+            let seconds = opts.number("--seconds")?.unwrap_or(3);
+            let addresses = opts.positional()?;
+            let [address] = addresses.as_slice() else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "usage: omaveil peek <addr> [--seconds <n>]",
+                ));
+            };
+            peek_window(address, seconds)?;
+        }
+        "cycle" => {
+            maybe_prune(opts.flag("--no-prune"))?;
+            opts.no_positional()?;
+            cycle_windows()?;
+        }
+        "restore-last" => {
+            maybe_prune(opts.flag("--no-prune"))?;
+            let options = RestoreOptions {
+                layout_hint: opts.flag("--layout-hint"),
+                origin_monitor: opts.flag("--origin-monitor") || config().restore_to_origin_monitor,
+                no_focus: opts.flag("--no-focus"),
+                ..Default::default()
+            };
+            check_no_focus(&options)?;
+            let class = opts.value("--class")?;
+            opts.no_positional()?;
+            restore_last(&options, class.as_deref())?;
+        }
+        "is-minimized" => {
+            let verify = opts.flag("--verify");
+            let as_json = opts.flag("--json");
+            let addresses = opts.positional()?;
+            let [address] = addresses.as_slice() else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "usage: omaveil is-minimized <addr> [--verify] [--json]",
+                ));
+            };
+            let entry = load_minimized_windows()?
+                .into_iter()
+                .find(|w| &w.address == address);
+            // Asks the backend, since not every one hides on a special
+            // workspace
+            let hidden = |w: &MinimizedWindow| {
+                !verify
+                    || clients().is_some_and(|live| {
+                        live.iter().any(|c| {
+                            c.address == w.address && backend().is_hidden(&c.workspace_name)
+                        })
+                    })
+            };
+            match entry.filter(hidden) {
+                Some(window) => {
+                    if as_json {
+                        println!("{}", window_to_json(&window));
+                    }
+                }
+                None => process::exit(1),
+            }
+        }
+        "kill" => {
+            let all = opts.flag("--all");
+            let yes = opts.flag("--yes");
+            let group = opts.value("--group")?;
+            let class = opts.value("--class")?;
+            let addresses = opts.positional()?;
+            let windows = load_minimized_windows()?;
+
+            let targets: Vec<MinimizedWindow> = if all {
+                if !addresses.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "kill --all takes no address",
+                    ));
+                }
+                let class = class.map(|c| c.to_lowercase());
+                windows
+                    .into_iter()
+                    .filter(|w| group.is_none() || w.group == group)
+                    .filter(|w| {
+                        class
+                            .as_ref()
+                            .is_none_or(|c| w.class.to_lowercase().contains(c))
+                    })
+                    .collect()
+            } else {
+                if group.is_some() || class.is_some() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "--group and --class only apply together with --all",
+                    ));
+                }
+                let [address] = addresses.as_slice() else {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "usage: omaveil kill <addr> | kill --all [--group <name>] [--class <class>] [--yes]",
+                    ));
+                };
+                let found: Vec<_> = windows
+                    .into_iter()
+                    .filter(|w| &w.address == address)
+                    .collect();
+                if found.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("{} is not minimized", address),
+                    ));
+                }
+                found
+            };
+
+            if targets.is_empty() {
+                say("No matching minimized windows");
+                return Ok(());
+            }
+            if all
+                && !yes
+                && !confirm(&format!(
+                    "Close {} minimized window(s)? Unsaved work in them is lost.",
+                    targets.len()
+                ))?
+            {
+                return Ok(());
+            }
+            let closed = kill_windows(&targets)?;
+            say(&format!("Closed {} window(s)", closed));
+        }
+        "prune" => {
+            let restore = opts.flag("--restore");
+            let orphans = opts.flag("--orphans");
+            let since = opts
+                .value("--since")?
+                .map(|s| parse_duration(&s))
+                .transpose()?;
+            opts.no_positional()?;
+            if orphans {
+                if since.is_some() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "--since doesn't apply to --orphans",
+                    ));
+                }
+                return prune_orphans(restore);
+            }
+            if since.is_some() && !restore {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "--since only applies together with --restore",
+                ));
+            }
+            prune(restore, since)?;
+        }
+        "reconcile" => {
+            opts.no_positional()?;
+            reconcile()?;
+        }
+        "list" => {
+            let class_filter = opts.value("--filter")?;
+            let title_match = opts
+                .value("--match")?
+                .map(|p| {
+                    Pattern::new(&p).map_err(|e| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("invalid --match pattern: {}", e),
+                        )
+                    })
+                })
+                .transpose()?;
+            let as_json = opts.flag("--json");
+            if opts.flag("--plain") && as_json {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "--json and --plain are mutually exclusive",
+                ));
+            }
+            opts.no_positional()?;
+            if !list_windows(class_filter.as_deref(), title_match.as_ref(), as_json)? {
+                process::exit(1);
+            }
+        }
+        "export-grid" => {
+            let columns = opts
+                .number("--columns")?
+                .unwrap_or(config().grid_columns)
+                .max(1);
+            let outputs = opts.positional()?;
+            let [output_path] = outputs.as_slice() else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "export-grid needs one output path, e.g. omaveil export-grid ~/minimized.png",
+                ));
+            };
+            export_grid(output_path, columns)?;
+        }
+        "trim-previews" => {
+            opts.no_positional()?;
+            trim_previews()?;
+        }
+        "pick" => {
+            let index = opts.flag("--index");
+            let json = opts.flag("--json");
+            opts.no_positional()?;
+            let format = match (index, json) {
+                (true, true) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "--index and --json are mutually exclusive",
+                    ))
+                }
+                (true, false) => "index",
+                (false, true) => "json",
+                (false, false) => "address",
+            };
+            pick_only(format)?;
         }
         "show" => {
-            show_status()?;
+            let icon_only = opts.flag("--icon-only");
+            let count_only = opts.flag("--count-only");
+            opts.no_positional()?;
+            let display = match (icon_only, count_only) {
+                (true, true) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "--icon-only and --count-only are mutually exclusive",
+                    ))
+                }
+                (true, false) => "icon",
+                (false, true) => "count",
+                (false, false) => "both",
+            };
+            show_status(display)?;
+        }
+        "version-check" => {
+            opts.no_positional()?;
+            version_check()?;
+        }
+        "doctor" => {
+            let fix = opts.flag("--fix");
+            opts.no_positional()?;
+            if !doctor(fix)? {
+                process::exit(1);
+            }
+        }
+        "watch" => {
+            opts.no_positional()?;
+            watch()?;
+        }
+        // Internal: the timer `minimize --ttl` starts
+        "expire" => {
+            let args = opts.positional()?;
+            let [address, at] = args.as_slice() else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "usage: omaveil expire <addr> <unix-time>",
+                ));
+            };
+            let at = at.parse().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid time {:?}", at),
+                )
+            })?;
+            expire(address, at)?;
+        }
+        "serve" => {
+            let socket = opts
+                .value("--socket")?
+                .map_or_else(default_socket_path, PathBuf::from);
+            opts.no_positional()?;
+            serve(&socket)?;
+        }
+        "config" => {
+            let effective = opts.flag("--effective");
+            let rest = opts.positional()?;
+            match rest.split_first() {
+                Some((sub, path)) if sub == "validate" && path.len() <= 1 => {
+                    if !validate_config(path.first().map(String::as_str))? {
+                        process::exit(1);
+                    }
+                }
+                Some((sub, rest)) if sub == "print" && rest.is_empty() => {
+                    print_config(effective);
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "usage: omaveil config validate [path] | config print [--effective]",
+                    ))
+                }
+            }
         }
         _ => {
             eprintln!("OmaVeil - Omarchy-native window minimizer for Hyprland");
             eprintln!();
-            eprintln!("Usage: omaveil <command> [window_address]");
+            eprintln!("Usage: omaveil [-q|-v] <command> [window_address]");
             eprintln!();
             eprintln!("Commands:");
-            eprintln!("  minimize       Hide the focused window into special:minimum");
+            eprintln!(
+                "  minimize [--capture-delay <ms>] [--group <name>] [--note <text>] [--select]"
+            );
+            eprintln!("                 Hide the focused (or, with --select, clicked) window");
+            eprintln!("  minimize --address <addr>");
+            eprintln!("                 Hide the window at <addr>, focused or not");
+            eprintln!("  minimize --class <class> [--all-workspaces]");
+            eprintln!("                 Hide every window of <class> on the active workspace");
+            eprintln!("                 (or on all of them)");
+            eprintln!("  minimize --title-match <regex> [--all-workspaces]");
+            eprintln!("                 Hide every window whose title matches, likewise");
+            eprintln!("  minimize --stdin");
+            eprintln!("                 Hide every window whose address is read from stdin,");
+            eprintln!("                 one per line");
+            eprintln!("  minimize --ttl <duration>");
+            eprintln!("                 Restore the window by itself after <duration> (25m, 1h);");
+            eprintln!("                 combines with the other ways of picking windows");
+            eprintln!("  minimize --no-preview");
+            eprintln!(
+                "                 Skip the thumbnail (grim + convert) for a faster minimize;"
+            );
+            eprintln!(
+                "                 minimize-all, minimize-others, toggle and swap take it too"
+            );
+            eprintln!("  minimize-all [--monitor <name>] [--exclude <class>]...");
+            eprintln!("                 Hide every window on the active workspace (or the one");
+            eprintln!("                 shown on <name>)");
+            eprintln!("  minimize-others [--exclude <class>]...");
+            eprintln!(
+                "                 Hide every window on the active workspace but the focused one"
+            );
             eprintln!("  restore        Open Walker dmenu picker to restore a window");
-            eprintln!("  restore [addr] Restore a specific window by address");
-            eprintln!("  restore-last   Restore the most recently minimized window");
-            eprintln!("  restore-all    Restore all minimized windows");
-            eprintln!("  show           Print Waybar-compatible JSON status");
+            eprintln!("  restore [addr]...");
+            eprintln!("                 Restore specific windows by address");
+            eprintln!("  restore --keep [addr]");
+            eprintln!("                 Restore but keep the entry, marked restored, until the");
+            eprintln!("                 window is minimized again");
+            eprintln!("  restore --query <text> [--first|--all]");
+            eprintln!(
+                "                 Restore the window whose class and title fuzzy-match <text>"
+            );
+            eprintln!("  restore --index <n>");
+            eprintln!("                 Restore the window <n> places down the stack (0 = latest)");
+            eprintln!("  restore --group <name>");
+            eprintln!("                 Restore every window minimized into the group together");
+            eprintln!("  restore --class <class> [--first|--all]");
+            eprintln!("                 Restore the minimized window of <class>");
+            eprintln!("  restore --title-match <regex> [--first|--all]");
+            eprintln!("                 Restore the minimized window whose title matches <regex>;");
+            eprintln!("                 combines with --class. When several windows match, the");
+            eprintln!("                 picker opens with just those, unless --first restores the");
+            eprintln!("                 best (or latest) one or --all restores them all");
+            eprintln!("  restore --toggle <addr|class>");
+            eprintln!("                 Restore the window if minimized, minimize it if restored");
+            eprintln!("  restore --print-geometry [--json] [addr]");
+            eprintln!("                 Also print the stored and restored geometry");
+            eprintln!("  restore --center-on-monitor <name> [addr]");
+            eprintln!("                 Restore floating, centered on monitor <name>");
+            eprintln!("  restore --into-group [addr]");
+            eprintln!("                 Restore into the focused window's tab group");
+            eprintln!("  restore --workspace <n|name> [addr]");
+            eprintln!("                 Restore to workspace <n|name> instead of the active one");
+            eprintln!("  restore-last [--class <class>]");
+            eprintln!("                 Restore the most recently minimized window still open");
+            eprintln!("                 (of <class>)");
+            eprintln!("  undo           Minimize the last restored window again");
+            eprintln!("  toggle [--capture-delay <ms>] [--group <name>]");
+            eprintln!("                 Minimize the focused window, or restore the last one");
+            eprintln!("                 when nothing has focus or the focused window's class");
+            eprintln!("                 matches it");
+            eprintln!("  swap [--capture-delay <ms>] [--group <name>]");
+            eprintln!("                 Minimize the focused window and restore the last one");
+            eprintln!("                 in its place");
+            eprintln!("  peek <addr> [--seconds <n>]");
+            eprintln!("                 Show a minimized window for a few seconds, then hide it");
+            eprintln!("  cycle          Restore the next minimized window, re-minimizing the one");
+            eprintln!("                 the previous cycle showed if it still has focus");
+            eprintln!("  restore-all [--group <name>] [--class <class>] [--delay <ms>]");
+            eprintln!(
+                "                 Restore all minimized windows (or only one group or class)"
+            );
+            eprintln!("  restore-all --original-workspaces");
+            eprintln!("                 Restore each window to the workspace it came from");
+            eprintln!("  restore-all --from-others");
+            eprintln!("                 Restore only what the last minimize-others hid");
+            eprintln!("  swap-group <show> <hide>");
+            eprintln!("                 Minimize the active workspace into <hide>, restore <show>");
+            eprintln!();
+            eprintln!("  restore, restore-last and restore-all accept --no-prune to skip the");
+            eprintln!("  check that drops entries for windows closed while minimized,");
+            eprintln!("  --layout-hint to put tiled windows back next to their old neighbor,");
+            eprintln!(
+                "  --origin-monitor to restore to the monitor the window was minimized from,"
+            );
+            eprintln!("  and --no-focus to restore without focusing the window.");
+            eprintln!("  restore-all --delay pauses between windows, for setups where Hyprland");
+            eprintln!("  drops moves that arrive back to back. Without --delay, --layout-hint,");
+            eprintln!("  --origin-monitor, --original-workspaces or --no-focus, restore-all (and");
+            eprintln!("  restore with several addresses) sends every move to Hyprland in one");
+            eprintln!("  batched request.");
             eprintln!();
-            eprintln!("Errors: {}", LOG_FILE);
+            eprintln!("  is-minimized <addr> [--verify] [--json]");
+            eprintln!("                 Exit 0 if the window is minimized, 1 otherwise");
+            eprintln!("  kill <addr>    Close a minimized window and forget it");
+            eprintln!("  kill --all [--group <name>] [--class <class>] [--yes]");
+            eprintln!("                 Close every (matching) minimized window, after confirming");
+            eprintln!("  prune [--restore [--since <age>]]");
+            eprintln!(
+                "                 Drop entries for closed windows; with --restore, also send"
+            );
+            eprintln!(
+                "                 windows (minimized longer than <age>) back to their workspace"
+            );
+            eprintln!("  prune --orphans [--restore]");
+            eprintln!(
+                "                 Re-add hidden windows missing from the state (or restore them)"
+            );
+            eprintln!("  reconcile      Resync the state with the windows Hyprland reports");
+            eprintln!("  list [--filter <class>] [--match <regex>] [--json|--plain]");
+            eprintln!("                 Print minimized windows; exits 1 if none match");
+            eprintln!("  export-grid <path> [--columns <n>]");
+            eprintln!("                 Write all thumbnails into one labelled contact sheet PNG");
+            eprintln!("  trim-previews  Delete thumbnails no minimized window refers to");
+            eprintln!("  pick [--index|--json]");
+            eprintln!(
+                "                 Open the picker and print the selection instead of restoring"
+            );
+            eprintln!("  show [--icon-only|--count-only]");
+            eprintln!("                 Print Waybar-compatible JSON status");
+            eprintln!("  version-check  Check whether a newer release is available");
+            eprintln!("  doctor [--fix] Diagnose (and repair) state, thumbnails and setup");
+            eprintln!("  watch          Drop entries as soon as their window closes and minimize");
+            eprintln!("                 idle windows (Hyprland only)");
+            eprintln!("  serve [--socket <path>]");
+            eprintln!("                 Answer JSON requests on a Unix socket");
+            eprintln!("  config validate [path]");
+            eprintln!("                 Check a config file and report every problem");
+            eprintln!("  config print [--effective]");
+            eprintln!(
+                "                 Print the resolved config (and where each value came from)"
+            );
+            eprintln!();
+            eprintln!("Global options:");
+            eprintln!("  -q, --quiet    No summary lines or desktop notifications");
+            eprintln!("  -v, --verbose  Print progress for minimize-all, restore-all and the like");
+            eprintln!("  --set <key>=<value>");
+            eprintln!("                 Override a config key for this run (repeatable)");
+            eprintln!("  --special-workspace <name>");
+            eprintln!("                 Hide windows on special:<name> instead of special:minimum");
+            eprintln!();
+            eprintln!("Errors: {}", paths().log_file.display());
         }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::MutexGuard;
+
+    /// Paths, config and the fake hyprctl are process-wide, so tests that
+    /// use them take turns.
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    const CLIENTS: &str = r#"[
+        {"address":"0xa1","class":"firefox","title":"web","at":[0,0],"size":[950,1080],"workspace":{"id":5,"name":"5"},"floating":false},
+        {"address":"0xa2","class":"kitty","title":"term","at":[970,0],"size":[950,540],"workspace":{"id":5,"name":"5"},"floating":false},
+        {"address":"0xa3","class":"Spotify","title":"music","at":[970,560],"size":[950,520],"workspace":{"id":5,"name":"5"},"floating":false},
+        {"address":"0xa4","class":"foot","title":"other","at":[0,0],"size":[950,1080],"workspace":{"id":2,"name":"2"},"floating":false}
+    ]"#;
+    const ACTIVE_WINDOW: &str = r#"{"address":"0xa2","class":"kitty","title":"term","at":[970,0],"size":[950,540],"workspace":{"id":5,"name":"5"},"floating":false}"#;
+    const MONITORS: &str = r#"[{"id":0,"name":"DP-1","width":1920,"height":1080,"scale":1.0,"x":0,"y":0,"transform":0,"activeWorkspace":{"id":5,"name":"5"}}]"#;
+
+    /// A clean slate: state in a fresh directory, nothing minimized, the
+    /// fake answering with the windows above and an empty dispatch log.
+    fn setup() -> MutexGuard<'static, ()> {
+        let guard = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let fake = hypr::fake_dir();
+        let root = fake.parent().unwrap().to_path_buf();
+        PATHS.get_or_init(|| {
+            Paths::new(
+                root.join("state"),
+                root.join("previews"),
+                root.join("omaveil.log"),
+            )
+        });
+        // No grim or convert in a test run
+        CONFIG.get_or_init(|| {
+            let mut config = Config::default();
+            config.previews = false;
+            // A dmenu-style picker that always takes the first line
+            config.picker = "head -n 1".to_string();
+            config
+        });
+
+        let _ = fs::remove_dir_all(&paths().cache_dir);
+        paths().ensure_writable().unwrap();
+        fs::create_dir_all(&fake).unwrap();
+        for (name, content) in [
+            ("clients.json", CLIENTS),
+            ("activewindow.json", ACTIVE_WINDOW),
+            ("activeworkspace.json", r#"{"id":5,"name":"5"}"#),
+            ("monitors.json", MONITORS),
+            (
+                "workspaces.json",
+                r#"[{"id":5,"name":"5"},{"id":2,"name":"2"},{"id":-1337,"name":"notes"}]"#,
+            ),
+            ("version.json", r#"{"tag":"v0.41.2"}"#),
+        ] {
+            fs::write(fake.join(name), content).unwrap();
+        }
+        let _ = fs::remove_file(fake.join("dispatch.log"));
+        guard
+    }
+
+    fn omaveil(args: &[&str]) -> io::Result<()> {
+        let argv: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        run(&argv)
+    }
+
+    /// What the fake was asked to dispatch, oldest first; cleared after.
+    fn dispatched() -> Vec<String> {
+        let log = hypr::fake_dir().join("dispatch.log");
+        let lines = fs::read_to_string(&log)
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_string)
+            .collect();
+        let _ = fs::remove_file(log);
+        lines
+    }
+
+    fn minimized() -> Vec<String> {
+        load_minimized_windows()
+            .unwrap()
+            .into_iter()
+            .map(|w| w.address)
+            .collect()
+    }
+
+    #[test]
+    fn minimize_hides_the_focused_window() {
+        let _serial = setup();
+        omaveil(&["minimize"]).unwrap();
+        assert_eq!(
+            dispatched(),
+            ["dispatch movetoworkspacesilent special:minimum,address:0xa2"]
+        );
+        assert_eq!(minimized(), ["0xa2"]);
+    }
+
+    #[test]
+    fn restore_brings_the_window_back_and_forgets_it() {
+        let _serial = setup();
+        omaveil(&["minimize"]).unwrap();
+        dispatched();
+        omaveil(&["restore", "--no-prune", "0xa2"]).unwrap();
+        assert_eq!(
+            dispatched(),
+            [
+                "dispatch movetoworkspace 5,address:0xa2",
+                "dispatch focuswindow address:0xa2",
+            ]
+        );
+        assert!(minimized().is_empty());
+    }
+
+    #[test]
+    fn minimize_class_batches_every_match_on_the_workspace() {
+        let _serial = setup();
+        omaveil(&["minimize", "--class", "i"]).unwrap();
+        assert_eq!(
+            dispatched(),
+            [
+                "dispatch movetoworkspacesilent special:minimum,address:0xa1",
+                "dispatch movetoworkspacesilent special:minimum,address:0xa2",
+                "dispatch movetoworkspacesilent special:minimum,address:0xa3",
+            ]
+        );
+        assert_eq!(minimized(), ["0xa1", "0xa2", "0xa3"]);
+    }
+
+    #[test]
+    fn legacy_state_with_a_trailing_backslash_is_read_and_migrated() {
+        let _serial = setup();
+        let legacy = concat!(
+            r#"[{"address":"0xa1","display_title":"C:\","class":"firefox","#,
+            r#""original_title":"C:\","workspace_id":5,"workspace_name":"5","floating":false},"#,
+            r#"{"address":"0xa2","display_title":"say \"hi\", ok","class":"kitty","#,
+            r#""original_title":"say \"hi\", ok","workspace_id":5,"workspace_name":"5","floating":true}]"#
+        );
+        fs::write(&paths().cache_file, legacy).unwrap();
+
+        let windows = load_state().unwrap();
+        let titles: Vec<_> = windows.iter().map(|w| w.display_title.as_str()).collect();
+        assert_eq!(titles, ["C:\\", "say \"hi\", ok"]);
+        assert_eq!(windows[1].floating, Some(true));
+
+        let rewritten = fs::read_to_string(&paths().cache_file).unwrap();
+        assert!(serde_json::from_str::<json::Value>(&rewritten).is_ok());
+        assert_eq!(parse_windows_from_json(&rewritten).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn state_entries_keep_their_flat_keys_and_survive_unknown_values() {
+        let window = MinimizedWindow {
+            address: "0xa1".to_string(),
+            display_title: "C:\\ \"x\"".to_string(),
+            geometry: Some(Rect {
+                x: 10,
+                y: 20,
+                width: 300,
+                height: 200,
+            }),
+            fullscreen: Some(Fullscreen::Maximized),
+            restored: true,
+            ..Default::default()
+        };
+        let text = window_to_json(&window);
+        let value: json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(value["preview"], "");
+        assert_eq!(value["x"], 10);
+        assert_eq!(value["height"], 200);
+        assert_eq!(value["fullscreen"], "maximized");
+        assert_eq!(value["state"], "restored");
+        assert!(value.get("pinned").is_none() && value.get("workspace_id").is_none());
+
+        let read = &parse_windows_from_json(&format!("[{}]", text)).unwrap()[0];
+        assert_eq!(read.display_title, window.display_title);
+        assert_eq!(read.geometry, window.geometry);
+        assert!(read.restored);
+
+        // A fullscreen kind from a newer version doesn't cost the window
+        let newer = r#"[{"address":"0xa2","fullscreen":"both","state":"minimized"}]"#;
+        let read = parse_windows_from_json(newer).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].fullscreen, None);
+        assert!(!read[0].restored);
+    }
+
+    fn monitor(name: &str, x: i64, width: i64, height: i64) -> Monitor {
+        Monitor {
+            name: name.to_string(),
+            active_workspace: 1,
+            transform: 0,
+            rect: Rect {
+                x,
+                y: 0,
+                width,
+                height,
+            },
+        }
+    }
+
+    #[test]
+    fn window_across_two_monitors_is_captured_on_the_one_it_overlaps_most() {
+        let monitors = [
+            monitor("DP-1", 0, 1920, 1080),
+            monitor("HDMI-A-1", 1920, 2560, 1440),
+        ];
+        let mostly_right = Rect {
+            x: 1500,
+            y: 100,
+            width: 1000,
+            height: 600,
+        };
+        assert_eq!(
+            monitor_for(&mostly_right, &monitors).map(|m| m.name.as_str()),
+            Some("HDMI-A-1")
+        );
+        assert_eq!(
+            preview_geometry(&mostly_right, &monitors),
+            Rect {
+                x: 1920,
+                y: 100,
+                width: 580,
+                height: 600,
+            }
+        );
+
+        // An even split goes to the monitor with the top-left corner
+        let halves = Rect {
+            x: 1420,
+            ..mostly_right
+        };
+        assert_eq!(
+            monitor_for(&halves, &monitors).map(|m| m.name.as_str()),
+            Some("DP-1")
+        );
+        assert_eq!(
+            preview_geometry(&halves, &monitors),
+            Rect {
+                x: 1420,
+                y: 100,
+                width: 500,
+                height: 600,
+            }
+        );
+    }
+
+    #[test]
+    fn failed_write_leaves_the_state_file_alone() {
+        let _serial = setup();
+        omaveil(&["minimize"]).unwrap();
+        let before = fs::read_to_string(&paths().cache_file).unwrap();
+
+        // A directory where the temp file goes makes the write fail even for
+        // root, which a read-only cache directory wouldn't
+        let tmp = paths()
+            .cache_dir
+            .join(format!(".windows.json.{}.tmp", process::id()));
+        fs::create_dir(&tmp).unwrap();
+        let result = save_minimized_windows(&[]);
+        fs::remove_dir(&tmp).unwrap();
+
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&paths().cache_file).unwrap(), before);
+        assert_eq!(minimized(), ["0xa2"]);
+    }
+
+    #[test]
+    fn invalid_utf8_in_a_title_becomes_a_replacement_character() {
+        let _serial = setup();
+        let (head, tail) = CLIENTS.split_once(r#""term""#).unwrap();
+        let reply = [head.as_bytes(), b"\"term \xff\xfe\"", tail.as_bytes()].concat();
+        fs::write(hypr::fake_dir().join("clients.json"), reply).unwrap();
+
+        let titles: Vec<String> = clients().unwrap().into_iter().map(|c| c.title).collect();
+        assert_eq!(titles, ["web", "term \u{fffd}\u{fffd}", "music", "other"]);
+
+        omaveil(&["minimize", "--address", "0xa2"]).unwrap();
+        let windows = load_minimized_windows().unwrap();
+        assert_eq!(windows.len(), 1);
+        assert_eq!(windows[0].address, "0xa2");
+        assert_eq!(windows[0].original_title, "term \u{fffd}\u{fffd}");
+    }
+
+    #[test]
+    fn empty_state_file_reads_as_an_empty_stack_and_is_rewritten() {
+        let _serial = setup();
+        fs::write(&paths().cache_file, "").unwrap();
+
+        assert!(load_state().unwrap().is_empty());
+        assert_eq!(fs::read_to_string(&paths().cache_file).unwrap(), "[]");
+        assert!(!paths().cache_file.with_extension("json.corrupt").exists());
+    }
+
+    #[test]
+    fn identical_picker_lines_are_told_apart_by_position() {
+        let lines: Vec<String> = ["kitty - term", "firefox - web", "kitty - term"]
+            .map(String::from)
+            .to_vec();
+        assert_eq!(
+            distinct_lines(&lines),
+            ["kitty - term (#1)", "firefox - web", "kitty - term (#3)"]
+        );
+    }
+
+    #[test]
+    fn pick_rejects_unknown_flags() {
+        let _serial = setup();
+        let err = omaveil(&["pick", "--idnex"]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn restore_into_group_merges_every_window() {
+        let _serial = setup();
+        omaveil(&["minimize", "--address", "0xa1"]).unwrap();
+        omaveil(&["minimize", "--address", "0xa3"]).unwrap();
+        dispatched();
+
+        omaveil(&["restore", "--no-prune", "--into-group", "0xa1", "0xa3"]).unwrap();
+        let grouped: Vec<String> = dispatched()
+            .into_iter()
+            .filter(|line| line.contains("group"))
+            .collect();
+        assert_eq!(
+            grouped,
+            [
+                "dispatch togglegroup ",
+                "dispatch moveintogroup r",
+                "dispatch togglegroup ",
+                "dispatch moveintogroup u",
+            ]
+        );
+        assert!(minimized().is_empty());
+    }
+
+    #[test]
+    fn restore_to_a_named_workspace_addresses_it_by_name() {
+        let _serial = setup();
+        omaveil(&["minimize"]).unwrap();
+        dispatched();
+        omaveil(&["restore", "--no-prune", "--workspace", "notes", "0xa2"]).unwrap();
+        assert_eq!(
+            dispatched(),
+            [
+                "dispatch movetoworkspace name:notes,address:0xa2",
+                "dispatch focuswindow address:0xa2",
+            ]
+        );
+    }
+
+    #[test]
+    fn pick_index_feeds_restore_index() {
+        let _serial = setup();
+        omaveil(&["minimize", "--address", "0xa1"]).unwrap();
+        omaveil(&["minimize", "--address", "0xa3"]).unwrap();
+        dispatched();
+
+        // The picker takes the first line, the oldest window
+        let index = picked_window("index").unwrap().unwrap();
+        assert_eq!(index, "1");
+        omaveil(&["restore", "--no-prune", "--index", &index]).unwrap();
+        assert_eq!(
+            dispatched(),
+            [
+                "dispatch movetoworkspace 5,address:0xa1",
+                "dispatch focuswindow address:0xa1",
+            ]
+        );
+        assert_eq!(minimized(), ["0xa3"]);
+    }
+
+    #[test]
+    fn stray_arguments_are_rejected() {
+        let _serial = setup();
+        for args in [
+            &["minimize", "0xa1"][..],
+            &["list", "firefox"],
+            &["undo", "now"],
+        ] {
+            let err = omaveil(args).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{:?}", args);
+        }
+        assert!(dispatched().is_empty());
+        assert!(minimized().is_empty());
+    }
+}
@@ -0,0 +1,381 @@
+// The `Backend` for niri, over its IPC socket (`$NIRI_SOCKET`), the same
+// one `niri msg` uses.
+//
+// A request is one line of JSON (`"Windows"`, `{"Action":{...}}`) and the
+// reply is one line holding `{"Ok":...}` or `{"Err":"reason"}`. Each request
+// gets its own connection.
+//
+// niri has no special workspaces, so minimized windows go to a named
+// workspace (`special_workspace`, `minimum` by default), which has to be
+// declared in niri's config so it exists on every start. Windows are addressed by their niri window id.
+// Workspace numbers are niri's workspace ids rather than their positions,
+// since those shift as workspaces are added and removed.
+
+use crate::{
+    backend::Backend,
+    json,
+    json::{Value, ValueExt},
+    Client, Monitor, Rect, WorkspaceTarget,
+};
+use std::{
+    env,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    time::Duration,
+};
+
+pub struct Niri {
+    path: PathBuf,
+}
+
+impl Niri {
+    /// The running niri session, if `$NIRI_SOCKET` points at a socket.
+    pub fn locate() -> Option<Niri> {
+        let path = PathBuf::from(env::var_os("NIRI_SOCKET").filter(|s| !s.is_empty())?);
+        path.exists().then_some(Niri { path })
+    }
+
+    fn request(&self, request: &str) -> io::Result<Result<Value, String>> {
+        crate::timed(&format!("niri {}", request), || {
+            let mut stream = UnixStream::connect(&self.path)?;
+            stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+            stream.write_all(format!("{}\n", request).as_bytes())?;
+
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line)?;
+            let reply = serde_json::from_str::<Value>(line.trim()).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("niri: could not parse reply — {}", e),
+                )
+            })?;
+            Ok(match (reply.get("Ok"), reply.get("Err")) {
+                (Some(ok), _) => Ok(ok.clone()),
+                (None, Some(err)) => Err(err.as_str().unwrap_or_default().to_string()),
+                (None, None) => Err(format!("unexpected reply {}", line.trim())),
+            })
+        })
+    }
+
+    /// Asks for `kind` ("Windows", "Workspaces", ...) and returns the
+    /// payload, or `None` (logged under `context`) if that failed.
+    fn query(&self, context: &str, kind: &str) -> Option<Value> {
+        let failure = match self.request(&format!("\"{}\"", kind)) {
+            Ok(Ok(reply)) => return reply.get(kind).cloned(),
+            Ok(Err(reason)) => reason,
+            Err(e) => e.to_string(),
+        };
+        crate::log_error(&format!("{}: niri {} failed — {}", context, kind, failure));
+        None
+    }
+
+    /// Runs one action, e.g. `{"FocusWindow":{"id":12}}`; true if niri
+    /// handled it. Rejections are logged under `context`.
+    fn action(&self, context: &str, action: &str) -> io::Result<bool> {
+        match self.request(&format!("{{\"Action\":{}}}", action))? {
+            Ok(_) => Ok(true),
+            Err(reason) => {
+                crate::log_error(&format!(
+                    "{}: niri rejected {} — {}",
+                    context, action, reason
+                ));
+                Ok(false)
+            }
+        }
+    }
+
+    fn workspaces(&self, context: &str) -> Vec<Workspace> {
+        self.query(context, "Workspaces")
+            .as_ref()
+            .map_or(&[][..], Value::items)
+            .iter()
+            .filter_map(|ws| {
+                Some(Workspace {
+                    id: ws.get("id")?.as_i64()?,
+                    name: ws
+                        .get("name")
+                        .and_then(Value::as_str)
+                        .map(str::to_string)
+                        // Unnamed workspaces go by their position
+                        .or_else(|| Some(ws.get("idx")?.as_i64()?.to_string()))
+                        .unwrap_or_default(),
+                    output: ws.str_field("output").to_string(),
+                    active: ws.get("is_active") == Some(&Value::Bool(true)),
+                    focused: ws.get("is_focused") == Some(&Value::Bool(true)),
+                })
+            })
+            .collect()
+    }
+
+    /// Logical position of each output, for turning window positions
+    /// (relative to their output) into layout coordinates.
+    fn outputs(&self, context: &str) -> Vec<Output> {
+        let Some(Value::Object(outputs)) = self.query(context, "Outputs") else {
+            return Vec::new();
+        };
+        outputs
+            .iter()
+            .filter_map(|(name, output)| {
+                let logical = output.get("logical")?;
+                let field = |key: &str| logical.get(key).and_then(Value::as_i64);
+                Some(Output {
+                    name: name.clone(),
+                    rect: Rect {
+                        x: field("x")?,
+                        y: field("y")?,
+                        width: field("width")?,
+                        height: field("height")?,
+                    },
+                    transform: transform_index(logical.str_field("transform")),
+                })
+            })
+            .collect()
+    }
+
+    fn client(&self, window: &Value, workspaces: &[Workspace], outputs: &[Output]) -> Client {
+        let workspace = window
+            .get("workspace_id")
+            .and_then(Value::as_i64)
+            .and_then(|id| workspaces.iter().find(|ws| ws.id == id));
+        let origin = workspace
+            .and_then(|ws| outputs.iter().find(|o| o.name == ws.output))
+            .map_or((0, 0), |o| (o.rect.x, o.rect.y));
+        Client {
+            address: window
+                .get("id")
+                .and_then(Value::as_i64)
+                .map_or_else(String::new, |id| id.to_string()),
+            class: window.str_field("app_id").to_string(),
+            title: window.str_field("title").to_string(),
+            workspace_id: workspace.map_or(0, |ws| ws.id),
+            workspace_name: workspace.map_or_else(String::new, |ws| ws.name.clone()),
+            floating: window.get("is_floating") == Some(&Value::Bool(true)),
+            rect: window
+                .get("layout")
+                .and_then(|layout| window_rect(layout, origin)),
+            // Not reported over IPC
+            fullscreen: None,
+            pinned: false,
+            grouped: false,
+        }
+    }
+}
+
+struct Workspace {
+    id: i64,
+    name: String,
+    output: String,
+    active: bool,
+    focused: bool,
+}
+
+struct Output {
+    name: String,
+    rect: Rect,
+    transform: i64,
+}
+
+// niri 25.05+ reports `layout.window_size` and, for windows that are on
+// screen, `layout.tile_pos_in_workspace_view`; older releases report neither
+fn window_rect(layout: &Value, (x, y): (i64, i64)) -> Option<Rect> {
+    let pair = |key: &str| -> Option<(f64, f64)> {
+        let items = layout.get(key)?.as_array()?;
+        Some((items.first()?.as_f64()?, items.get(1)?.as_f64()?))
+    };
+    let (left, top) = pair("tile_pos_in_workspace_view")?;
+    let (width, height) = pair("window_size")?;
+    let rect = Rect {
+        x: x + left.round() as i64,
+        y: y + top.round() as i64,
+        width: width.round() as i64,
+        height: height.round() as i64,
+    };
+    (rect.width > 0 && rect.height > 0).then_some(rect)
+}
+
+// "Normal", "_90", "Flipped270", ... -> wl_output's 0-7
+fn transform_index(name: &str) -> i64 {
+    let (flipped, rotation) = match name.strip_prefix("Flipped") {
+        Some(rest) => (4, rest),
+        None => (0, name.trim_start_matches('_')),
+    };
+    flipped
+        + match rotation {
+            "90" => 1,
+            "180" => 2,
+            "270" => 3,
+            _ => 0,
+        }
+}
+
+impl Backend for Niri {
+    fn name(&self) -> &'static str {
+        "niri"
+    }
+
+    fn hidden_workspace(&self) -> String {
+        crate::config().special_workspace.clone()
+    }
+
+    fn clients(&self) -> Option<Vec<Client>> {
+        let windows = self.query("clients", "Windows")?;
+        let workspaces = self.workspaces("clients");
+        let outputs = self.outputs("clients");
+        Some(
+            windows
+                .items()
+                .iter()
+                .map(|w| self.client(w, &workspaces, &outputs))
+                .collect(),
+        )
+    }
+
+    fn monitors(&self) -> Vec<Monitor> {
+        let workspaces = self.workspaces("monitors");
+        self.outputs("monitors")
+            .into_iter()
+            .map(|output| Monitor {
+                active_workspace: workspaces
+                    .iter()
+                    .find(|ws| ws.active && ws.output == output.name)
+                    .map_or(0, |ws| ws.id),
+                name: output.name,
+                transform: output.transform,
+                // Logical already, rotation included
+                rect: output.rect,
+            })
+            .collect()
+    }
+
+    fn active_window(&self, context: &str) -> io::Result<Option<Client>> {
+        let Some(window) = self.query(context, "FocusedWindow") else {
+            return Ok(None);
+        };
+        // `null` when nothing has focus
+        if window == Value::Null {
+            return Ok(None);
+        }
+        let client = self.client(&window, &self.workspaces(context), &self.outputs(context));
+        Ok(Some(client).filter(|c| !c.address.is_empty()))
+    }
+
+    fn active_workspace(&self, context: &str) -> io::Result<Option<i64>> {
+        Ok(self
+            .workspaces(context)
+            .iter()
+            .find(|ws| ws.focused)
+            .map(|ws| ws.id))
+    }
+
+    fn workspace_named(&self, context: &str, name: &str) -> io::Result<Option<WorkspaceTarget>> {
+        Ok(self
+            .workspaces(context)
+            .iter()
+            .find(|ws| ws.name == name)
+            .map(|ws| WorkspaceTarget::Number(ws.id)))
+    }
+
+    // Moving to another workspace never switches the view there unless
+    // asked, so `follow` changes nothing
+    fn hide(
+        &self,
+        address: &str,
+        workspace: &str,
+        _follow: bool,
+    ) -> io::Result<Result<(), String>> {
+        let action = format!(
+            "{{\"MoveWindowToWorkspace\":{{\"window_id\":{},\"reference\":{{\"Name\":\"{}\"}},\"focus\":false}}}}",
+            address, workspace
+        );
+        Ok(match self.request(&format!("{{\"Action\":{}}}", action))? {
+            Ok(_) => Ok(()),
+            Err(reason) if reason.contains("workspace") => Err(format!(
+                "{} — is `workspace \"{}\"` declared in niri's config?",
+                reason, workspace
+            )),
+            Err(reason) => Err(reason),
+        })
+    }
+
+    fn move_to_workspace(
+        &self,
+        context: &str,
+        address: &str,
+        workspace: &WorkspaceTarget,
+        silent: bool,
+    ) -> io::Result<bool> {
+        let reference = match workspace {
+            WorkspaceTarget::Number(id) => format!("{{\"Id\":{}}}", id),
+            WorkspaceTarget::Named(name) => format!("{{\"Name\":\"{}\"}}", json::escape(name)),
+        };
+        self.action(
+            context,
+            &format!(
+                "{{\"MoveWindowToWorkspace\":{{\"window_id\":{},\"reference\":{},\"focus\":{}}}}}",
+                address, reference, !silent
+            ),
+        )
+    }
+
+    fn focus(&self, context: &str, address: &str) -> io::Result<bool> {
+        self.action(
+            context,
+            &format!("{{\"FocusWindow\":{{\"id\":{}}}}}", address),
+        )
+    }
+
+    fn close(&self, context: &str, address: &str) -> io::Result<bool> {
+        self.action(
+            context,
+            &format!("{{\"CloseWindow\":{{\"id\":{}}}}}", address),
+        )
+    }
+
+    fn float_at(
+        &self,
+        context: &str,
+        address: &str,
+        floating: bool,
+        rect: Rect,
+    ) -> io::Result<bool> {
+        if !floating
+            && !self.action(
+                context,
+                &format!("{{\"MoveWindowToFloating\":{{\"id\":{}}}}}", address),
+            )?
+        {
+            return Ok(false);
+        }
+        let fixed = |value: i64| format!("{{\"SetFixed\":{}}}", value);
+        // Floating positions are relative to the window's output
+        let origin = self
+            .outputs(context)
+            .into_iter()
+            .find(|o| o.rect.contains(rect.x, rect.y))
+            .map_or((0, 0), |o| (o.rect.x, o.rect.y));
+        for action in [
+            format!(
+                "{{\"SetWindowWidth\":{{\"id\":{},\"change\":{}}}}}",
+                address,
+                fixed(rect.width)
+            ),
+            format!(
+                "{{\"SetWindowHeight\":{{\"id\":{},\"change\":{}}}}}",
+                address,
+                fixed(rect.height)
+            ),
+            format!(
+                "{{\"MoveFloatingWindow\":{{\"id\":{},\"x\":{},\"y\":{}}}}}",
+                address,
+                fixed(rect.x - origin.0),
+                fixed(rect.y - origin.1)
+            ),
+        ] {
+            if !self.action(context, &action)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
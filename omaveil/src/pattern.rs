@@ -0,0 +1,436 @@
+// A small backtracking regex matcher for title/class filters.
+//
+// Supports what people actually type into a keybind: literals, `.`, `[...]`
+// classes (ranges, negation), `\d \w \s` and their negations, `^`/`$`, groups
+// with `|`, and the `* + ? {n} {n,} {n,m}` quantifiers. A leading `(?i)` makes
+// the whole pattern case-insensitive. Matching is unanchored like `grep`.
+//
+// `fuzzy_score` is the looser, picker-style match used by `restore --query`.
+
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    alternatives: Vec<Vec<Node>>,
+    case_insensitive: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Char(char),
+    Any,
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    Start,
+    End,
+    Group(Vec<Vec<Node>>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: usize,
+    },
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Result<Pattern, String> {
+        let (case_insensitive, body) = match pattern.strip_prefix("(?i)") {
+            Some(rest) => (true, rest),
+            None => (false, pattern),
+        };
+        let mut parser = Parser {
+            chars: body.chars().collect(),
+            pos: 0,
+        };
+        let alternatives = parser.alternatives()?;
+        if parser.pos < parser.chars.len() {
+            return Err(format!("unmatched ')' at position {}", parser.pos));
+        }
+        Ok(Pattern {
+            alternatives,
+            case_insensitive,
+        })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        (0..=text.len()).any(|start| {
+            self.alternatives
+                .iter()
+                .any(|seq| self.match_seq(seq, &text, start, &mut |_| true))
+        })
+    }
+
+    fn match_seq(
+        &self,
+        seq: &[Node],
+        text: &[char],
+        pos: usize,
+        k: &mut dyn FnMut(usize) -> bool,
+    ) -> bool {
+        match seq.split_first() {
+            None => k(pos),
+            Some((node, rest)) => {
+                self.match_node(node, text, pos, &mut |p| self.match_seq(rest, text, p, k))
+            }
+        }
+    }
+
+    fn match_node(
+        &self,
+        node: &Node,
+        text: &[char],
+        pos: usize,
+        k: &mut dyn FnMut(usize) -> bool,
+    ) -> bool {
+        match node {
+            Node::Start => pos == 0 && k(pos),
+            Node::End => pos == text.len() && k(pos),
+            Node::Group(alternatives) => {
+                for seq in alternatives {
+                    if self.match_seq(seq, text, pos, k) {
+                        return true;
+                    }
+                }
+                false
+            }
+            Node::Repeat { node, min, max } => self.match_repeat(node, *min, *max, text, pos, k),
+            single => match text.get(pos) {
+                Some(&c) if self.matches_char(single, c) => k(pos + 1),
+                _ => false,
+            },
+        }
+    }
+
+    // Greedy: try to take one more repetition before settling for fewer.
+    fn match_repeat(
+        &self,
+        node: &Node,
+        min: usize,
+        max: usize,
+        text: &[char],
+        pos: usize,
+        k: &mut dyn FnMut(usize) -> bool,
+    ) -> bool {
+        if max > 0
+            && self.match_node(node, text, pos, &mut |p| {
+                // An empty repetition can't make progress, so stop looping on
+                // it; it can still stand in for every one that's required
+                if p == pos {
+                    return min > 0 && k(p);
+                }
+                self.match_repeat(node, min.saturating_sub(1), max - 1, text, p, k)
+            })
+        {
+            return true;
+        }
+        min == 0 && k(pos)
+    }
+
+    fn matches_char(&self, node: &Node, c: char) -> bool {
+        match node {
+            Node::Any => c != '\n',
+            Node::Char(expected) => {
+                *expected == c || (self.case_insensitive && fold(*expected) == fold(c))
+            }
+            Node::Class { ranges, negated } => {
+                let hit = |c: char| ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&c));
+                let found = hit(c)
+                    || (self.case_insensitive && (hit(fold(c)) || c.to_uppercase().any(hit)));
+                found != *negated
+            }
+            _ => false,
+        }
+    }
+}
+
+fn fold(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn alternatives(&mut self) -> Result<Vec<Vec<Node>>, String> {
+        let mut alternatives = vec![self.sequence()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            alternatives.push(self.sequence()?);
+        }
+        Ok(alternatives)
+    }
+
+    fn sequence(&mut self) -> Result<Vec<Node>, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.quantified(atom)?);
+        }
+        Ok(nodes)
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let start = self.pos;
+        match self.next() {
+            Some('.') => Ok(Node::Any),
+            Some('^') => Ok(Node::Start),
+            Some('$') => Ok(Node::End),
+            Some('(') => {
+                // Non-capturing groups look the same to us
+                if self.chars[self.pos..].starts_with(&['?', ':']) {
+                    self.pos += 2;
+                }
+                let alternatives = self.alternatives()?;
+                if self.next() != Some(')') {
+                    return Err(format!("unclosed group opened at position {}", start));
+                }
+                Ok(Node::Group(alternatives))
+            }
+            Some('[') => self.class(start),
+            Some('\\') => self.escape(),
+            Some(c @ ('*' | '+' | '?')) => Err(format!(
+                "nothing to repeat before '{}' at position {}",
+                c, start
+            )),
+            Some(c) => Ok(Node::Char(c)),
+            None => Err("unexpected end of pattern".to_string()),
+        }
+    }
+
+    fn escape(&mut self) -> Result<Node, String> {
+        let shorthand = |ranges: &[(char, char)], negated| Node::Class {
+            ranges: ranges.to_vec(),
+            negated,
+        };
+        const DIGIT: &[(char, char)] = &[('0', '9')];
+        const WORD: &[(char, char)] = &[('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')];
+        const SPACE: &[(char, char)] = &[(' ', ' '), ('\t', '\r')];
+        match self.next() {
+            Some('d') => Ok(shorthand(DIGIT, false)),
+            Some('D') => Ok(shorthand(DIGIT, true)),
+            Some('w') => Ok(shorthand(WORD, false)),
+            Some('W') => Ok(shorthand(WORD, true)),
+            Some('s') => Ok(shorthand(SPACE, false)),
+            Some('S') => Ok(shorthand(SPACE, true)),
+            Some('n') => Ok(Node::Char('\n')),
+            Some('t') => Ok(Node::Char('\t')),
+            Some(c) => Ok(Node::Char(c)),
+            None => Err("pattern ends with a dangling backslash".to_string()),
+        }
+    }
+
+    fn class(&mut self, start: usize) -> Result<Node, String> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let c = match self.next() {
+                None => return Err(format!("unclosed '[' at position {}", start)),
+                Some(']') if !first => break,
+                Some('\\') => self
+                    .next()
+                    .ok_or_else(|| "pattern ends with a dangling backslash".to_string())?,
+                Some(c) => c,
+            };
+            first = false;
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&n| n != ']') {
+                self.pos += 1;
+                let hi = match self.next() {
+                    Some('\\') => self.next().unwrap_or('\\'),
+                    Some(hi) => hi,
+                    None => return Err(format!("unclosed '[' at position {}", start)),
+                };
+                if hi < c {
+                    return Err(format!("invalid range {}-{} in class", c, hi));
+                }
+                ranges.push((c, hi));
+            } else {
+                ranges.push((c, c));
+            }
+        }
+        Ok(Node::Class { ranges, negated })
+    }
+
+    fn quantified(&mut self, atom: Node) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, usize::MAX),
+            Some('+') => (1, usize::MAX),
+            Some('?') => (0, 1),
+            Some('{') => match self.braces()? {
+                Some(bounds) => return Ok(repeat(atom, bounds)),
+                None => return Ok(atom),
+            },
+            _ => return Ok(atom),
+        };
+        self.pos += 1;
+        if matches!(atom, Node::Start | Node::End) {
+            return Err("anchors can't be repeated".to_string());
+        }
+        Ok(repeat(atom, (min, max)))
+    }
+
+    // `{n}`, `{n,}` or `{n,m}`; a `{` that isn't a valid bound is a literal.
+    fn braces(&mut self) -> Result<Option<(usize, usize)>, String> {
+        let rest: String = self.chars[self.pos + 1..].iter().collect();
+        let Some(end) = rest.find('}') else {
+            return Ok(None);
+        };
+        let body = &rest[..end];
+        let parse = |s: &str| s.trim().parse::<usize>().ok();
+        let bounds = match body.split_once(',') {
+            None => parse(body).map(|n| (n, n)),
+            Some((lo, "")) => parse(lo).map(|n| (n, usize::MAX)),
+            Some((lo, hi)) => parse(lo).zip(parse(hi)),
+        };
+        match bounds {
+            Some((min, max)) if min > max => Err(format!("invalid repeat {{{}}}", body)),
+            Some(bounds) => {
+                self.pos += body.chars().count() + 2;
+                Ok(Some(bounds))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+fn repeat(atom: Node, (min, max): (usize, usize)) -> Node {
+    Node::Repeat {
+        node: Box::new(atom),
+        min,
+        max,
+    }
+}
+
+/// How well `query` fuzzy-matches `text`, case-insensitively: `None` unless
+/// its characters all appear in `text` in order, otherwise higher for runs
+/// of consecutive characters and matches at word starts, lower for gaps.
+pub fn fuzzy_score(query: &str, text: &str) -> Option<i64> {
+    let query: Vec<char> = query.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let Some(&first) = query.first() else {
+        return Some(0);
+    };
+
+    // Greedy from every place the first character occurs, keeping the best
+    (0..text.len())
+        .filter(|&start| text[start] == first)
+        .filter_map(|start| {
+            let mut score = 0;
+            let mut previous: Option<usize> = None;
+            let mut at = start;
+            for &wanted in &query {
+                let found = (at..text.len()).find(|&i| text[i] == wanted)?;
+                score += 16;
+                if found == 0 || !text[found - 1].is_alphanumeric() {
+                    score += 10;
+                }
+                match previous {
+                    Some(p) if found == p + 1 => score += 8,
+                    Some(p) => score -= (found - p - 1) as i64,
+                    None => {}
+                }
+                previous = Some(found);
+                at = found + 1;
+            }
+            Some(score)
+        })
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Pattern::new(pattern).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn classes_cover_ranges_negation_and_shorthands() {
+        assert!(matches("[a-c]x", "bx"));
+        assert!(!matches("[a-c]x", "dx"));
+        assert!(matches("[^0-9]", "a"));
+        assert!(!matches("^[^0-9]+$", "a1"));
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[a-]", "-"));
+        assert!(matches(r"[\]]", "]"));
+        assert!(matches(r"^\d\d:\d\d$", "12:30"));
+        assert!(matches(r"^\w+\s\S$", "foo_1 x"));
+        assert!(!matches(r"\D", "123"));
+    }
+
+    #[test]
+    fn anchors_pin_the_match_to_either_end() {
+        assert!(matches("^fire", "firefox"));
+        assert!(!matches("^fox", "firefox"));
+        assert!(matches("fox$", "firefox"));
+        assert!(!matches("fire$", "firefox"));
+        assert!(matches("^$", ""));
+        assert!(!matches("^$", "x"));
+    }
+
+    #[test]
+    fn alternation_applies_at_the_top_and_inside_groups() {
+        assert!(matches("kitty|foot", "foot"));
+        assert!(!matches("^(kitty|foot)$", "footer"));
+        assert!(matches("^(?:a|bc)+$", "abca"));
+        assert!(matches("^(a|)b$", "b"));
+    }
+
+    #[test]
+    fn quantifiers_respect_their_bounds() {
+        assert!(matches("^a{2}$", "aa"));
+        assert!(!matches("^a{2}$", "aaa"));
+        assert!(matches("^a{2,}$", "aaaa"));
+        assert!(!matches("^a{2,}$", "a"));
+        assert!(matches("^a{1,3}b$", "aaab"));
+        assert!(!matches("^a{1,3}b$", "aaaab"));
+        assert!(matches("^ab?c$", "ac"));
+        assert!(matches("^a+$", "aaa"));
+        assert!(!matches("^a+$", ""));
+        // Not a valid bound, so the brace is a literal
+        assert!(matches("^a{x}$", "a{x}"));
+    }
+
+    #[test]
+    fn a_leading_case_flag_folds_literals_and_classes() {
+        assert!(matches("(?i)FireFox", "firefox"));
+        assert!(matches("(?i)^[a-z]+$", "KITTY"));
+        assert!(matches("(?i)ÉCOLE", "école"));
+        assert!(!matches("FireFox", "firefox"));
+    }
+
+    #[test]
+    fn repeats_of_something_that_can_match_empty_terminate_and_match() {
+        assert!(matches("^(a?){2}$", ""));
+        assert!(matches("^(a?){2}$", "a"));
+        assert!(matches("^(a*)*$", "aaa"));
+        assert!(matches("^(a|)+b$", "b"));
+        assert!(!matches("^(a?){2}$", "aaa"));
+    }
+
+    #[test]
+    fn malformed_patterns_are_rejected() {
+        for pattern in ["(ab", "ab)", "[ab", "*a", "a{3,1}", "^*", "a\\"] {
+            assert!(Pattern::new(pattern).is_err(), "{}", pattern);
+        }
+    }
+}
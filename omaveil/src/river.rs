@@ -0,0 +1,214 @@
+// The `Backend` for river, built on `riverctl` for actions and `lswt -j`
+// for listing windows.
+//
+// river can't address a window by id: every riverctl command acts on the
+// focused view. So to act on a particular window, OmaVeil cycles focus with
+// `focus-view next` until `lswt` (see `crate::toplevel`) reports that window
+// as activated. Windows are addressed by their ext-foreign-toplevel
+// identifier.
+//
+// Minimizing moves the window to a tag of its own (the highest, bit 31)
+// that is never focused. river doesn't report which tags a window is on,
+// so windows on the hidden tag are known from the state file instead.
+// Tag masks stand in for workspace numbers, with 0 meaning "the focused
+// tags": restoring briefly shows the hidden tag alongside them, picks the
+// window out and sends it to the previously focused tags.
+
+use crate::{backend::Backend, toplevel::lswt, Client, Monitor, Rect, WorkspaceTarget};
+use std::{io, process::Command};
+
+/// The tag minimized windows are parked on.
+const HIDDEN_TAG: u32 = 1 << 31;
+
+/// `workspace_name` reported for windows on the hidden tag.
+const HIDDEN: &str = "hidden";
+
+pub struct River;
+
+impl River {
+    /// Whether this looks like a river session. river exports nothing of
+    /// its own, so this goes by `$XDG_CURRENT_DESKTOP`.
+    pub fn detect() -> bool {
+        std::env::var("XDG_CURRENT_DESKTOP")
+            .is_ok_and(|d| d.split(':').any(|d| d.eq_ignore_ascii_case("river")))
+    }
+
+    /// Runs `riverctl <args>`; true if it succeeded. Failures are logged
+    /// under `context`.
+    fn riverctl(&self, context: &str, args: &[&str]) -> io::Result<bool> {
+        let output = crate::timed(&format!("riverctl {}", args.join(" ")), || {
+            Command::new("riverctl").args(args).output()
+        })?;
+        if !output.status.success() {
+            crate::log_error(&format!(
+                "{}: riverctl {} failed — {}",
+                context,
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(output.status.success())
+    }
+
+    /// Every toplevel `lswt` lists, with whether it's the focused one.
+    fn toplevels(&self, context: &str) -> Option<Vec<(Client, bool)>> {
+        let toplevels = lswt(context)?;
+        let hidden: Vec<String> = crate::load_state()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|w| !w.restored)
+            .map(|w| w.address)
+            .collect();
+        Some(
+            toplevels
+                .into_iter()
+                .map(|t| {
+                    let on_hidden = hidden.contains(&t.identifier);
+                    let client = Client {
+                        address: t.identifier,
+                        class: t.app_id,
+                        title: t.title,
+                        workspace_id: if on_hidden { HIDDEN_TAG as i64 } else { 0 },
+                        workspace_name: if on_hidden { HIDDEN } else { "" }.to_string(),
+                        floating: false,
+                        rect: None,
+                        fullscreen: None,
+                        pinned: false,
+                        grouped: false,
+                    };
+                    (client, t.activated)
+                })
+                .collect(),
+        )
+    }
+
+    /// Cycles focus through the visible views until `address` has it.
+    /// False if it never came round.
+    fn focus_by_cycling(&self, context: &str, address: &str) -> io::Result<bool> {
+        let Some(toplevels) = self.toplevels(context) else {
+            return Ok(false);
+        };
+        for _ in 0..=toplevels.len() {
+            let focused = self.toplevels(context).unwrap_or_default();
+            if focused
+                .iter()
+                .any(|(c, active)| *active && c.address == address)
+            {
+                return Ok(true);
+            }
+            if !self.riverctl(context, &["focus-view", "next"])? {
+                return Ok(false);
+            }
+        }
+        crate::log_error(&format!(
+            "{}: address={} is not on the focused tags",
+            context, address
+        ));
+        Ok(false)
+    }
+}
+
+impl Backend for River {
+    fn name(&self) -> &'static str {
+        "river"
+    }
+
+    fn hidden_workspace(&self) -> String {
+        HIDDEN.to_string()
+    }
+
+    fn clients(&self) -> Option<Vec<Client>> {
+        let toplevels = self.toplevels("clients")?;
+        Some(toplevels.into_iter().map(|(c, _)| c).collect())
+    }
+
+    // river has no output query; features that need monitors report none
+    fn monitors(&self) -> Vec<Monitor> {
+        Vec::new()
+    }
+
+    fn active_window(&self, context: &str) -> io::Result<Option<Client>> {
+        Ok(self
+            .toplevels(context)
+            .unwrap_or_default()
+            .into_iter()
+            .find(|(_, active)| *active)
+            .map(|(c, _)| c))
+    }
+
+    fn active_workspace(&self, _context: &str) -> io::Result<Option<i64>> {
+        Ok(Some(0))
+    }
+
+    fn hide(
+        &self,
+        address: &str,
+        _workspace: &str,
+        _follow: bool,
+    ) -> io::Result<Result<(), String>> {
+        if !self.focus_by_cycling("minimize", address)? {
+            return Ok(Err("window is not on the focused tags".to_string()));
+        }
+        Ok(
+            match self.riverctl("minimize", &["set-view-tags", &HIDDEN_TAG.to_string()])? {
+                true => Ok(()),
+                false => Err("riverctl set-view-tags failed".to_string()),
+            },
+        )
+    }
+
+    // river never switches tags to follow a moved view, so `silent` changes
+    // nothing
+    fn move_to_workspace(
+        &self,
+        context: &str,
+        address: &str,
+        workspace: &WorkspaceTarget,
+        _silent: bool,
+    ) -> io::Result<bool> {
+        // Tags have no names
+        let WorkspaceTarget::Number(workspace) = *workspace else {
+            return Ok(false);
+        };
+        let hidden = HIDDEN_TAG.to_string();
+        if !self.riverctl(context, &["toggle-focused-tags", &hidden])? {
+            return Ok(false);
+        }
+        // Either way, show only the tags that were focused before
+        let moved = self.focus_by_cycling(context, address)?
+            && match workspace {
+                0 => self.riverctl(context, &["send-to-previous-tags"])?,
+                tags => self.riverctl(context, &["set-view-tags", &tags.to_string()])?,
+            };
+        Ok(self.riverctl(context, &["toggle-focused-tags", &hidden])? && moved)
+    }
+
+    fn focus(&self, context: &str, address: &str) -> io::Result<bool> {
+        self.focus_by_cycling(context, address)
+    }
+
+    fn close(&self, context: &str, address: &str) -> io::Result<bool> {
+        let hidden = HIDDEN_TAG.to_string();
+        let on_hidden = crate::load_state()?
+            .iter()
+            .any(|w| w.address == address && !w.restored);
+        if on_hidden && !self.riverctl(context, &["toggle-focused-tags", &hidden])? {
+            return Ok(false);
+        }
+        let closed =
+            self.focus_by_cycling(context, address)? && self.riverctl(context, &["close"])?;
+        if on_hidden {
+            self.riverctl(context, &["toggle-focused-tags", &hidden])?;
+        }
+        Ok(closed)
+    }
+
+    // riverctl can only move and resize by offsets, not to a position
+    fn float_at(&self, context: &str, address: &str, _: bool, _: Rect) -> io::Result<bool> {
+        crate::log_error(&format!(
+            "{}: can't place address={} at a position on river",
+            context, address
+        ));
+        Ok(false)
+    }
+}
@@ -0,0 +1,411 @@
+// The `Backend` for Sway, over its i3-compatible IPC socket (`$SWAYSOCK`).
+//
+// Every message is the magic `i3-ipc`, then the payload length and message
+// type as native-endian u32s, then the payload; replies have the same
+// framing and a JSON body. Each request gets its own connection, like the
+// Hyprland socket transport.
+//
+// Windows are addressed by their container id, so a state entry's address
+// is just that number. Minimized windows live in the scratchpad
+// (`__i3_scratch`); tiled ones get a `_omaveil_tiled_<id>` mark on the way
+// in so restoring can tile them again, since the scratchpad floats
+// everything it holds.
+
+use crate::{
+    backend::Backend,
+    json::{Value, ValueExt},
+    Client, Fullscreen, Monitor, Rect, WorkspaceTarget,
+};
+use std::{
+    collections::HashMap,
+    env, io,
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    time::Duration,
+};
+
+const RUN_COMMAND: u32 = 0;
+const GET_WORKSPACES: u32 = 1;
+const GET_OUTPUTS: u32 = 3;
+const GET_TREE: u32 = 4;
+
+const MAGIC: &[u8] = b"i3-ipc";
+
+pub struct Sway {
+    path: PathBuf,
+}
+
+impl Sway {
+    /// The running Sway session, if `$SWAYSOCK` points at a socket.
+    pub fn locate() -> Option<Sway> {
+        let path = PathBuf::from(env::var_os("SWAYSOCK").filter(|s| !s.is_empty())?);
+        path.exists().then_some(Sway { path })
+    }
+
+    fn request(&self, kind: u32, payload: &str) -> io::Result<Value> {
+        let mut stream = UnixStream::connect(&self.path)?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+        let mut message = MAGIC.to_vec();
+        message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+        message.extend_from_slice(&kind.to_ne_bytes());
+        message.extend_from_slice(payload.as_bytes());
+        stream.write_all(&message)?;
+
+        let mut header = [0u8; 14];
+        stream.read_exact(&mut header)?;
+        if &header[..6] != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "sway: reply without the i3-ipc magic",
+            ));
+        }
+        let length = u32::from_ne_bytes([header[6], header[7], header[8], header[9]]);
+        let mut body = vec![0u8; length as usize];
+        stream.read_exact(&mut body)?;
+
+        serde_json::from_str::<Value>(&String::from_utf8_lossy(&body)).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("sway: could not parse reply — {}", e),
+            )
+        })
+    }
+
+    fn query(&self, context: &str, kind: u32) -> Option<Value> {
+        match timed_request(self, kind, "") {
+            Ok(value) => Some(value),
+            Err(e) => {
+                crate::log_error(&format!("{}: sway IPC request failed — {}", context, e));
+                None
+            }
+        }
+    }
+
+    /// Runs a sway command list; true if every command in it succeeded.
+    /// Failures are logged under `context`.
+    fn command(&self, context: &str, command: &str) -> io::Result<bool> {
+        let reply = timed_request(self, RUN_COMMAND, command)?;
+        let errors = rejections(&reply);
+        if !errors.is_empty() {
+            crate::log_error(&format!(
+                "{}: sway rejected `{}` — {}",
+                context,
+                command,
+                errors.join(" | ")
+            ));
+        }
+        Ok(errors.is_empty())
+    }
+
+    fn tree(&self, context: &str) -> Option<Vec<Window>> {
+        let tree = self.query(context, GET_TREE)?;
+        let mut windows = Vec::new();
+        collect_windows(&tree, None, &mut windows);
+        Some(windows)
+    }
+}
+
+// The reasons sway gave for each command in a list that failed
+fn rejections(reply: &Value) -> Vec<&str> {
+    reply
+        .items()
+        .iter()
+        .filter(|r| r.get("success") != Some(&Value::Bool(true)))
+        .map(|r| r.str_field("error"))
+        .collect()
+}
+
+fn timed_request(sway: &Sway, kind: u32, payload: &str) -> io::Result<Value> {
+    let label = match payload {
+        "" => format!("sway ipc {}", kind),
+        command => format!("sway ipc {} {}", kind, command),
+    };
+    crate::timed(&label, || sway.request(kind, payload))
+}
+
+/// A leaf of the layout tree, with what `Client` needs from its ancestors.
+struct Window {
+    client: Client,
+    focused: bool,
+}
+
+// Walks the tree depth-first. Leaves are containers with no children that
+// hold a client (`pid` is set); everything else just passes its workspace
+// down.
+fn collect_windows(node: &Value, workspace: Option<(&str, i64)>, out: &mut Vec<Window>) {
+    let workspace = match node.str_field("type") {
+        "workspace" => Some((
+            node.str_field("name"),
+            node.get("num").and_then(Value::as_i64).unwrap_or(-1),
+        )),
+        _ => workspace,
+    };
+    let children: Vec<&Value> = ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|key| node.get(key).and_then(Value::as_array))
+        .flatten()
+        .collect();
+
+    if children.is_empty() && node.get("pid").is_some_and(|p| p.as_i64().is_some()) {
+        let (workspace_name, workspace_id) = workspace.unwrap_or_default();
+        let class = match node.get("app_id").and_then(Value::as_str) {
+            Some(app_id) if !app_id.is_empty() => app_id,
+            // XWayland clients only have X11 properties
+            _ => node
+                .get("window_properties")
+                .map_or("", |p| p.str_field("class")),
+        };
+        out.push(Window {
+            client: Client {
+                address: node
+                    .get("id")
+                    .and_then(Value::as_i64)
+                    .map_or_else(String::new, |id| id.to_string()),
+                class: class.to_string(),
+                title: node.str_field("name").to_string(),
+                workspace_id,
+                workspace_name: workspace_name.to_string(),
+                floating: node.str_field("type") == "floating_con",
+                rect: node.get("rect").and_then(rect_from_json),
+                // Sway has no maximize; 1 is workspace and 2 global fullscreen
+                fullscreen: node
+                    .get("fullscreen_mode")
+                    .and_then(Value::as_i64)
+                    .is_some_and(|mode| mode > 0)
+                    .then_some(Fullscreen::Full),
+                pinned: node.get("sticky") == Some(&Value::Bool(true)),
+                // Tabbed containers aren't tracked
+                grouped: false,
+            },
+            focused: node.get("focused") == Some(&Value::Bool(true)),
+        });
+        return;
+    }
+
+    for child in children {
+        collect_windows(child, workspace, out);
+    }
+}
+
+fn rect_from_json(rect: &Value) -> Option<Rect> {
+    let field = |key: &str| rect.get(key).and_then(Value::as_i64);
+    let rect = Rect {
+        x: field("x")?,
+        y: field("y")?,
+        width: field("width")?,
+        height: field("height")?,
+    };
+    (rect.width > 0 && rect.height > 0).then_some(rect)
+}
+
+// sway names output transforms; map them to wl_output's 0-7
+fn transform_index(name: &str) -> i64 {
+    let (flipped, rotation) = match name.strip_prefix("flipped") {
+        Some(rest) => (4, rest.trim_start_matches('-')),
+        None => (0, name),
+    };
+    flipped
+        + match rotation {
+            "90" => 1,
+            "180" => 2,
+            "270" => 3,
+            _ => 0,
+        }
+}
+
+fn tiled_mark(address: &str) -> String {
+    format!("_omaveil_tiled_{}", address)
+}
+
+impl Backend for Sway {
+    fn name(&self) -> &'static str {
+        "Sway"
+    }
+
+    fn hidden_workspace(&self) -> String {
+        "__i3_scratch".to_string()
+    }
+
+    fn clients(&self) -> Option<Vec<Client>> {
+        let windows = self.tree("clients")?;
+        Some(windows.into_iter().map(|w| w.client).collect())
+    }
+
+    fn monitors(&self) -> Vec<Monitor> {
+        let Some(outputs) = self.query("monitors", GET_OUTPUTS) else {
+            return Vec::new();
+        };
+        let numbers: HashMap<String, i64> = self
+            .query("monitors", GET_WORKSPACES)
+            .as_ref()
+            .map_or(&[][..], Value::items)
+            .iter()
+            .filter_map(|ws| Some((ws.str_field("name").to_string(), ws.get("num")?.as_i64()?)))
+            .collect();
+
+        outputs
+            .items()
+            .iter()
+            .filter(|o| o.get("active") == Some(&Value::Bool(true)))
+            .filter_map(|o| {
+                Some(Monitor {
+                    name: o.str_field("name").to_string(),
+                    active_workspace: numbers
+                        .get(o.str_field("current_workspace"))
+                        .copied()
+                        .unwrap_or(0),
+                    transform: transform_index(o.str_field("transform")),
+                    // Already in logical coordinates, rotation included
+                    rect: rect_from_json(o.get("rect")?)?,
+                })
+            })
+            .collect()
+    }
+
+    fn active_window(&self, context: &str) -> io::Result<Option<Client>> {
+        let Some(windows) = self.tree(context) else {
+            return Ok(None);
+        };
+        Ok(windows
+            .into_iter()
+            .find(|w| w.focused)
+            .map(|w| w.client)
+            .filter(|c| !c.address.is_empty()))
+    }
+
+    fn active_workspace(&self, context: &str) -> io::Result<Option<i64>> {
+        let Some(workspaces) = self.query(context, GET_WORKSPACES) else {
+            return Ok(None);
+        };
+        Ok(workspaces
+            .items()
+            .iter()
+            .find(|ws| ws.get("focused") == Some(&Value::Bool(true)))
+            .and_then(|ws| ws.get("num").and_then(Value::as_i64)))
+    }
+
+    // Workspaces without a leading number report `num` -1
+    fn workspace_named(&self, context: &str, name: &str) -> io::Result<Option<WorkspaceTarget>> {
+        Ok(self
+            .query(context, GET_WORKSPACES)
+            .as_ref()
+            .map_or(&[][..], Value::items)
+            .iter()
+            .find(|ws| ws.str_field("name") == name)
+            .and_then(|ws| ws.get("num")?.as_i64())
+            .map(|num| match num {
+                num if num >= 0 => WorkspaceTarget::Number(num),
+                _ => WorkspaceTarget::Named(name.to_string()),
+            }))
+    }
+
+    // The scratchpad has no "follow" animation, so `follow` changes nothing
+    fn hide(
+        &self,
+        address: &str,
+        _workspace: &str,
+        _follow: bool,
+    ) -> io::Result<Result<(), String>> {
+        let floating = self
+            .clients()
+            .unwrap_or_default()
+            .iter()
+            .any(|c| c.address == address && c.floating);
+        let command = if floating {
+            format!("[con_id={}] move scratchpad", address)
+        } else {
+            format!(
+                "[con_id={}] mark --add {}, move scratchpad",
+                address,
+                tiled_mark(address)
+            )
+        };
+
+        let reply = timed_request(self, RUN_COMMAND, &command)?;
+        let errors = rejections(&reply);
+        Ok(match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors.join(" | ")),
+        })
+    }
+
+    fn move_to_workspace(
+        &self,
+        context: &str,
+        address: &str,
+        workspace: &WorkspaceTarget,
+        silent: bool,
+    ) -> io::Result<bool> {
+        let target = match workspace {
+            WorkspaceTarget::Number(number) => format!("number {}", number),
+            WorkspaceTarget::Named(name) => format!("\"{}\"", name.replace('"', "\\\"")),
+        };
+        let mut command = format!(
+            "[con_id={}] move container to workspace {}",
+            address, target
+        );
+        if !silent {
+            command.push_str(&format!(", [con_id={}] focus", address));
+        }
+        if !self.command(context, &command)? {
+            return Ok(false);
+        }
+
+        // Tile it again if it was tiled before it went into the scratchpad.
+        // No window carries the mark when it wasn't, which sway reports as
+        // a failed match; that's expected, so the result is ignored
+        let mark = tiled_mark(address);
+        timed_request(
+            self,
+            RUN_COMMAND,
+            &format!(
+                "[con_mark=\"^{}$\"] floating disable, unmark {}",
+                mark, mark
+            ),
+        )?;
+        Ok(true)
+    }
+
+    fn focus(&self, context: &str, address: &str) -> io::Result<bool> {
+        self.command(context, &format!("[con_id={}] focus", address))
+    }
+
+    fn set_fullscreen(&self, context: &str, address: &str, mode: Fullscreen) -> io::Result<bool> {
+        if mode != Fullscreen::Full {
+            return Ok(false);
+        }
+        self.command(context, &format!("[con_id={}] fullscreen enable", address))
+    }
+
+    fn pin(&self, context: &str, address: &str) -> io::Result<bool> {
+        self.command(context, &format!("[con_id={}] sticky enable", address))
+    }
+
+    fn unpin(&self, context: &str, address: &str) -> io::Result<bool> {
+        self.command(context, &format!("[con_id={}] sticky disable", address))
+    }
+
+    fn close(&self, context: &str, address: &str) -> io::Result<bool> {
+        self.command(context, &format!("[con_id={}] kill", address))
+    }
+
+    fn float_at(
+        &self,
+        context: &str,
+        address: &str,
+        floating: bool,
+        rect: Rect,
+    ) -> io::Result<bool> {
+        let mut command = format!("[con_id={}] ", address);
+        if !floating {
+            command.push_str("floating enable, ");
+        }
+        command.push_str(&format!(
+            "resize set width {} px height {} px, move absolute position {} {}",
+            rect.width, rect.height, rect.x, rect.y
+        ));
+        self.command(context, &command)
+    }
+}
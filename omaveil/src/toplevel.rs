@@ -0,0 +1,219 @@
+// The compositor-agnostic `Backend`, for wlroots compositors OmaVeil has no
+// IPC support for (labwc, Wayfire, ...). It goes through the
+// foreign-toplevel protocols every such compositor offers to taskbars:
+// `lswt -j` lists the windows and `wlrctl toplevel` minimizes, activates and
+// closes them.
+//
+// Minimizing is the protocol's own minimize, so hidden windows are the ones
+// the compositor reports as minimized, and where they go (and whether a
+// taskbar still shows them) is up to the compositor. Windows are addressed
+// by their ext-foreign-toplevel identifier, but wlrctl picks windows by
+// app id and title, so acting on one of two windows that share both may
+// hit the other too. There are no workspaces: 0 stands for "wherever the
+// compositor puts an unminimized window".
+//
+// `lswt()` is shared with the river backend, which lists windows the same
+// way.
+
+use crate::{
+    backend::Backend,
+    json::{Value, ValueExt},
+    Client, Monitor, Rect, WorkspaceTarget,
+};
+use std::{io, process::Command};
+
+/// `workspace_name` reported for minimized windows.
+const MINIMIZED: &str = "minimized";
+
+/// One entry of `lswt -j`.
+pub struct Toplevel {
+    pub identifier: String,
+    pub app_id: String,
+    pub title: String,
+    pub activated: bool,
+    pub minimized: bool,
+}
+
+/// Every window `lswt` can address, or `None` (logged under `context`) if
+/// it couldn't be run or its output didn't parse.
+pub fn lswt(context: &str) -> Option<Vec<Toplevel>> {
+    let output = match crate::timed("lswt -j", || Command::new("lswt").arg("-j").output()) {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            crate::log_error(&format!(
+                "{}: lswt failed — {}",
+                context,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+            return None;
+        }
+        Err(e) => {
+            crate::log_error(&format!("{}: failed to run lswt — {}", context, e));
+            return None;
+        }
+    };
+    let value = match serde_json::from_str::<Value>(&String::from_utf8_lossy(&output.stdout)) {
+        Ok(value) => value,
+        Err(e) => {
+            crate::log_error(&format!("{}: could not parse lswt output — {}", context, e));
+            return None;
+        }
+    };
+
+    let flag = |t: &Value, key: &str| t.get(key) == Some(&Value::Bool(true));
+    Some(
+        value
+            .get("toplevels")
+            .map_or(&[][..], Value::items)
+            .iter()
+            // Without ext-foreign-toplevel-list there is nothing stable to
+            // address the window by
+            .filter(|t| !t.str_field("identifier").is_empty())
+            .map(|t| Toplevel {
+                identifier: t.str_field("identifier").to_string(),
+                app_id: t.str_field("app-id").to_string(),
+                title: t.str_field("title").to_string(),
+                activated: flag(t, "activated"),
+                minimized: flag(t, "minimized"),
+            })
+            .collect(),
+    )
+}
+
+pub struct ForeignToplevel;
+
+impl ForeignToplevel {
+    /// Whether the tools this backend needs are installed.
+    pub fn available() -> bool {
+        std::env::var_os("WAYLAND_DISPLAY").is_some()
+            && crate::find_in_path("lswt")
+            && crate::find_in_path("wlrctl")
+    }
+
+    /// Runs `wlrctl toplevel <action>` on the window at `address`; true if
+    /// wlrctl found it and succeeded. Failures are logged under `context`.
+    fn wlrctl(&self, context: &str, action: &str, address: &str) -> io::Result<bool> {
+        let Some(toplevel) = lswt(context)
+            .unwrap_or_default()
+            .into_iter()
+            .find(|t| t.identifier == address)
+        else {
+            crate::log_error(&format!(
+                "{}: wlrctl {}: no window with address={}",
+                context, action, address
+            ));
+            return Ok(false);
+        };
+
+        let app_id = format!("app_id:{}", toplevel.app_id);
+        let title = format!("title:{}", toplevel.title);
+        let args = ["toplevel", action, app_id.as_str(), title.as_str()];
+        let output = crate::timed(&format!("wlrctl {}", args.join(" ")), || {
+            Command::new("wlrctl").args(args).output()
+        })?;
+        if !output.status.success() {
+            crate::log_error(&format!(
+                "{}: wlrctl {} failed — {}",
+                context,
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(output.status.success())
+    }
+}
+
+impl Backend for ForeignToplevel {
+    fn name(&self) -> &'static str {
+        "wlroots"
+    }
+
+    fn hidden_workspace(&self) -> String {
+        MINIMIZED.to_string()
+    }
+
+    fn clients(&self) -> Option<Vec<Client>> {
+        let toplevels = lswt("clients")?;
+        Some(toplevels.into_iter().map(client).collect())
+    }
+
+    // The protocol doesn't describe outputs; features that need monitors
+    // report none
+    fn monitors(&self) -> Vec<Monitor> {
+        Vec::new()
+    }
+
+    fn active_window(&self, context: &str) -> io::Result<Option<Client>> {
+        Ok(lswt(context)
+            .unwrap_or_default()
+            .into_iter()
+            .find(|t| t.activated)
+            .map(client))
+    }
+
+    fn active_workspace(&self, _context: &str) -> io::Result<Option<i64>> {
+        Ok(Some(0))
+    }
+
+    // The compositor decides how minimizing looks, so `follow` changes
+    // nothing
+    fn hide(
+        &self,
+        address: &str,
+        _workspace: &str,
+        _follow: bool,
+    ) -> io::Result<Result<(), String>> {
+        Ok(match self.wlrctl("minimize", "minimize", address)? {
+            true => Ok(()),
+            false => Err(format!("wlrctl could not minimize address={}", address)),
+        })
+    }
+
+    fn move_to_workspace(
+        &self,
+        context: &str,
+        address: &str,
+        _workspace: &WorkspaceTarget,
+        silent: bool,
+    ) -> io::Result<bool> {
+        if !self.wlrctl(context, "unminimize", address)? {
+            return Ok(false);
+        }
+        if silent {
+            return Ok(true);
+        }
+        self.focus(context, address)
+    }
+
+    fn focus(&self, context: &str, address: &str) -> io::Result<bool> {
+        self.wlrctl(context, "focus", address)
+    }
+
+    fn close(&self, context: &str, address: &str) -> io::Result<bool> {
+        self.wlrctl(context, "close", address)
+    }
+
+    // The protocol has no say over position or size
+    fn float_at(&self, context: &str, address: &str, _: bool, _: Rect) -> io::Result<bool> {
+        crate::log_error(&format!(
+            "{}: can't place address={} through foreign-toplevel",
+            context, address
+        ));
+        Ok(false)
+    }
+}
+
+fn client(toplevel: Toplevel) -> Client {
+    Client {
+        address: toplevel.identifier,
+        class: toplevel.app_id,
+        title: toplevel.title,
+        workspace_id: 0,
+        workspace_name: if toplevel.minimized { MINIMIZED } else { "" }.to_string(),
+        floating: false,
+        rect: None,
+        fullscreen: None,
+        pinned: false,
+        grouped: false,
+    }
+}